@@ -170,6 +170,14 @@ impl std::str::FromStr for FullViewingKey {
     }
 }
 
+impl FullViewingKeyHash {
+    /// A short, non-sensitive fingerprint of the full viewing key, suitable for
+    /// display, e.g. to confirm that the expected wallet is loaded.
+    pub fn fingerprint(&self) -> [u8; 8] {
+        self.0[..8].try_into().expect("hash is 32 bytes")
+    }
+}
+
 impl TryFrom<pb::FullViewingKeyHash> for FullViewingKeyHash {
     type Error = anyhow::Error;
 
@@ -204,3 +212,24 @@ impl std::fmt::Display for FullViewingKeyHash {
         f.write_str(&hex::encode(&self.0))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::keys::{SeedPhrase, SpendKey};
+
+    #[test]
+    fn fingerprint_is_stable_and_distinguishes_keys() {
+        let mut rng = rand::rngs::OsRng;
+        let seed_phrase = SeedPhrase::generate(&mut rng);
+        let sk = SpendKey::from_seed_phrase(SeedPhrase(seed_phrase.0.clone()), 0);
+        let same_sk = SpendKey::from_seed_phrase(seed_phrase, 0);
+        let other_sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+
+        let fvk = sk.full_viewing_key();
+        let same_fvk = same_sk.full_viewing_key();
+        let other_fvk = other_sk.full_viewing_key();
+
+        assert_eq!(fvk.hash().fingerprint(), same_fvk.hash().fingerprint());
+        assert_ne!(fvk.hash().fingerprint(), other_fvk.hash().fingerprint());
+    }
+}
@@ -17,9 +17,16 @@ use crate::{
 
 pub const MEMO_CIPHERTEXT_LEN_BYTES: usize = 528;
 
-// This is the `MEMO_CIPHERTEXT_LEN_BYTES` - MAC size (16 bytes).
+/// The maximum length of a memo, in bytes; shorter memos are padded with zeroes.
+///
+/// This is the `MEMO_CIPHERTEXT_LEN_BYTES` - MAC size (16 bytes).
 pub const MEMO_LEN_BYTES: usize = 512;
 
+/// The prefix of the memo attached to change outputs, identifying them as change when scanned.
+///
+/// Since the byte `0xff` never occurs in UTF-8, this cannot collide with a text memo.
+pub const CHANGE_MEMO_PREFIX: &[u8] = b"\xffchange";
+
 /// The nonce used for memo encryption.
 pub static MEMO_ENCRYPTION_NONCE: Lazy<[u8; 12]> = Lazy::new(|| {
     let nonce_bytes = 1u128.to_le_bytes();
@@ -41,7 +48,11 @@ impl TryFrom<&[u8]> for MemoPlaintext {
 
     fn try_from(input: &[u8]) -> Result<MemoPlaintext, Self::Error> {
         if input.len() > MEMO_LEN_BYTES {
-            return Err(anyhow::anyhow!("provided memo exceeds maximum memo size"));
+            return Err(anyhow::anyhow!(
+                "provided memo is {} bytes, exceeding the maximum memo size of {} bytes",
+                input.len(),
+                MEMO_LEN_BYTES
+            ));
         }
         let mut mp = [0u8; MEMO_LEN_BYTES];
         mp[..input.len()].copy_from_slice(input);
@@ -51,6 +62,18 @@ impl TryFrom<&[u8]> for MemoPlaintext {
 }
 
 impl MemoPlaintext {
+    /// The memo attached to change outputs: [`CHANGE_MEMO_PREFIX`], padded with zeroes.
+    pub fn change() -> MemoPlaintext {
+        CHANGE_MEMO_PREFIX
+            .try_into()
+            .expect("change memo prefix fits in a memo")
+    }
+
+    /// Whether this is the memo attached to change outputs.
+    pub fn is_change(&self) -> bool {
+        *self == MemoPlaintext::change()
+    }
+
     /// Encrypt a memo, returning its ciphertext.
     pub fn encrypt(&self, esk: &ka::Secret, address: &Address) -> MemoCiphertext {
         let epk = esk.diversified_public(address.diversified_generator());
@@ -205,4 +228,34 @@ mod tests {
 
         assert_eq!(plaintext, memo);
     }
+
+    #[test]
+    fn change_memo_is_recognized_after_decryption() {
+        let mut rng = OsRng;
+
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let ivk = sk.full_viewing_key().incoming();
+        let (dest, _dtk_d) = ivk.payment_address(0u64.into());
+
+        let esk = ka::Secret::new(&mut rng);
+        let ciphertext = MemoPlaintext::change().encrypt(&esk, &dest);
+
+        let epk = esk.diversified_public(dest.diversified_generator());
+        let plaintext = MemoPlaintext::decrypt(ciphertext, ivk, &epk).expect("can decrypt memo");
+
+        assert!(plaintext.is_change());
+        assert!(!MemoPlaintext::try_from("change".as_bytes())
+            .unwrap()
+            .is_change());
+    }
+
+    #[test]
+    fn text_memo_is_padded_or_rejected_by_length() {
+        let memo = MemoPlaintext::try_from("rent payment".as_bytes()).unwrap();
+        assert_eq!(&memo.0[.."rent payment".len()], b"rent payment");
+        assert!(memo.0["rent payment".len()..].iter().all(|&byte| byte == 0));
+
+        assert!(MemoPlaintext::try_from(&[b'a'; MEMO_LEN_BYTES][..]).is_ok());
+        assert!(MemoPlaintext::try_from(&[b'a'; MEMO_LEN_BYTES + 1][..]).is_err());
+    }
 }
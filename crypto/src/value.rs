@@ -6,9 +6,10 @@ use std::{
     str::FromStr,
 };
 
-use ark_ff::PrimeField;
+use ark_ff::{PrimeField, UniformRand};
 use once_cell::sync::Lazy;
 use penumbra_proto::{crypto as pb, Protobuf};
+use rand_core::{CryptoRng, RngCore};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use thiserror;
@@ -90,6 +91,13 @@ impl Value {
             )
         })
     }
+
+    /// Use the provided [`asset::Cache`] to format this value, falling back to the raw amount and
+    /// asset ID if the denomination is not known.
+    pub fn format(&self, cache: &asset::Cache) -> String {
+        self.try_format(cache)
+            .unwrap_or_else(|| format!("{} unknown:{}", self.amount, self.asset_id))
+    }
 }
 
 impl FromStr for Value {
@@ -116,6 +124,28 @@ impl FromStr for Value {
     }
 }
 
+/// Commit to the total of `values` by summing a commitment to each value under a fresh blinding
+/// factor, returning the sum and the summed blinding factor.
+///
+/// Since value commitments are homomorphic, if the values are all of one asset, the sum is a
+/// commitment to their total which the returned blinding factor opens, without revealing the
+/// individual values.
+pub fn commit_to_total<R: RngCore + CryptoRng>(
+    values: impl IntoIterator<Item = Value>,
+    mut rng: R,
+) -> (Commitment, Fr) {
+    values.into_iter().fold(
+        (Commitment::default(), Fr::from(0u64)),
+        |(commitment, blinding), value| {
+            let value_blinding = Fr::rand(&mut rng);
+            (
+                commitment + value.commit(value_blinding),
+                blinding + value_blinding,
+            )
+        },
+    )
+}
+
 impl std::ops::Add<Commitment> for Commitment {
     type Output = Commitment;
     fn add(self, rhs: Commitment) -> Self::Output {
@@ -299,4 +329,40 @@ mod tests {
         assert_eq!(v2.try_format(&cache).unwrap(), "1mpenumbra");
         assert_eq!(v3.try_format(&cache).unwrap(), "4penumbra");
     }
+
+    #[test]
+    fn total_commitment_opens_to_total() {
+        let values = ["30upenumbra", "12upenumbra", "100upenumbra"]
+            .iter()
+            .map(|value| value.parse::<Value>().unwrap())
+            .collect::<Vec<_>>();
+
+        let (commitment, blinding) = commit_to_total(values.clone(), rand_core::OsRng);
+
+        let total = Value {
+            amount: 142,
+            asset_id: values[0].asset_id,
+        };
+        assert_eq!(total.commit(blinding), commitment);
+        assert_ne!(
+            Value {
+                amount: 141,
+                ..total
+            }
+            .commit(blinding),
+            commitment
+        );
+    }
+
+    #[test]
+    fn format_falls_back_for_unknown_asset() {
+        let cache = asset::Cache::default();
+        let value: Value = "1000upenumbra".parse().unwrap();
+
+        assert!(value.try_format(&cache).is_none());
+        assert_eq!(
+            value.format(&cache),
+            format!("1000 unknown:{}", value.asset_id)
+        );
+    }
 }
@@ -13,6 +13,7 @@ pub use detection::DetectionKey;
 pub use error::Error;
 
 /// A clue that allows probabilistic message detection.
+#[derive(Clone, Debug)]
 pub struct Clue(pub [u8; 68]);
 
 /// The maximum detection precision, chosen so that the message bits fit in 3 bytes.
@@ -154,8 +154,10 @@ impl StakeCmd {
                     &[delegation_value],
                     *fee,
                     self_address,
-                    *source,
-                    None,
+                    plan::SendOptions {
+                        source_address: *source,
+                        ..Default::default()
+                    },
                 )
                 .await?;
 
@@ -279,9 +281,9 @@ impl StakeCmd {
 
                     table.add_row(vec![
                         info.validator.name.clone(),
-                        unbonded.try_format(&asset_cache).unwrap(),
+                        unbonded.format(&asset_cache),
                         format!("{:.4}", rate),
-                        delegation.try_format(&asset_cache).unwrap(),
+                        delegation.format(&asset_cache),
                     ]);
 
                     total += unbonded.amount;
@@ -301,9 +303,9 @@ impl StakeCmd {
 
                 table.add_row(vec![
                     "Unbonded Stake".to_string(),
-                    unbonded.try_format(&asset_cache).unwrap(),
+                    unbonded.format(&asset_cache),
                     format!("{:.4}", 1.0),
-                    unbonded.try_format(&asset_cache).unwrap(),
+                    unbonded.format(&asset_cache),
                 ]);
 
                 let total = Value {
@@ -313,7 +315,7 @@ impl StakeCmd {
 
                 table.add_row(vec![
                     "Total".to_string(),
-                    total.try_format(&asset_cache).unwrap(),
+                    total.format(&asset_cache),
                     String::new(),
                     String::new(),
                 ]);
@@ -60,7 +60,7 @@ impl TxCmd {
                     .collect::<Result<Vec<Value>, _>>()?;
                 let to = to
                     .parse()
-                    .map_err(|_| anyhow::anyhow!("address is invalid"))?;
+                    .map_err(|_| plan::PlanError::InvalidAddress(to.clone()))?;
 
                 let plan = plan::send(
                     &app.fvk,
@@ -69,8 +69,11 @@ impl TxCmd {
                     &values,
                     *fee,
                     to,
-                    *from,
-                    memo.clone(),
+                    plan::SendOptions {
+                        source_address: *from,
+                        tx_memo: memo.clone(),
+                        ..Default::default()
+                    },
                 )
                 .await?;
                 app.build_and_submit_transaction(plan).await?;
@@ -51,6 +51,9 @@ pub struct Opt {
     /// If set, use a remote view service instead of local synchronization.
     #[clap(short, long, env = "PENUMBRA_VIEW_ADDRESS")]
     view_address: Option<SocketAddr>,
+    /// If set, check the local view database's stored nullifiers against the wallet before use.
+    #[clap(long)]
+    verify_view: bool,
     /// The filter for `pcli`'s log messages.
     #[clap( long, default_value_t = EnvFilter::new("warn"), env = "RUST_LOG")]
     trace_filter: EnvFilter,
@@ -132,6 +135,7 @@ impl Opt {
                 self.node.to_string(),
                 self.pd_port,
                 self.tendermint_port,
+                self.verify_view,
             )
             .await?;
 
@@ -306,6 +306,7 @@ pub fn write_configs(
     let mut validator_spend_key_file = File::create(validator_spend_key_file_path)?;
     let validator_wallet = KeyStore {
         spend_key: vk.validator_spend_key.clone().into(),
+        ephemeral: false,
     };
     validator_spend_key_file
         .write_all(serde_json::to_string_pretty(&validator_wallet)?.as_bytes())?;
@@ -2,6 +2,8 @@ use std::convert::{TryFrom, TryInto};
 
 use anyhow::Error;
 use bytes::Bytes;
+#[cfg(feature = "fmd-clues")]
+use penumbra_crypto::fmd;
 use penumbra_crypto::{
     memo::MemoCiphertext, note, proofs::transparent::OutputProof, value, NotePayload,
 };
@@ -11,6 +13,12 @@ use penumbra_proto::{transaction as pb, Protobuf};
 pub struct Output {
     pub body: Body,
     pub proof: OutputProof,
+    /// A fuzzy message detection clue for the recipient's detection key.
+    ///
+    /// Clues aren't part of the wire format yet, so this is dropped when the output is encoded,
+    /// and is absent from decoded outputs.
+    #[cfg(feature = "fmd-clues")]
+    pub clue: Option<fmd::Clue>,
 }
 
 #[derive(Clone, Debug)]
@@ -45,6 +53,8 @@ impl TryFrom<pb::Output> for Output {
             proof: proto.zkproof[..]
                 .try_into()
                 .map_err(|_| anyhow::anyhow!("output body malformed"))?,
+            #[cfg(feature = "fmd-clues")]
+            clue: None,
         })
     }
 }
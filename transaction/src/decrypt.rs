@@ -0,0 +1,135 @@
+use penumbra_crypto::{keys::IncomingViewingKey, memo::MemoPlaintext, Note};
+use penumbra_proto::Protobuf;
+
+use crate::{Action, Transaction};
+
+/// An output of a transaction, decrypted using an incoming viewing key.
+#[derive(Clone, Debug)]
+pub struct DecryptedOutput {
+    /// The index of the output among the transaction's actions.
+    pub action_index: usize,
+    /// The note created by the output.
+    pub note: Note,
+    /// The memo attached to the output.
+    pub memo: MemoPlaintext,
+}
+
+impl Transaction {
+    /// Decrypt those outputs of this transaction which pay addresses viewed by `ivk`.
+    ///
+    /// Unlike scanning, this needs no state, so it can be used to inspect individual transactions,
+    /// e.g. in a block explorer.
+    pub fn decrypt_outputs(&self, ivk: &IncomingViewingKey) -> Vec<DecryptedOutput> {
+        self.actions()
+            .enumerate()
+            .filter_map(|(action_index, action)| {
+                let output = match action {
+                    Action::Output(output) => output,
+                    _ => return None,
+                };
+                let payload = &output.body.note_payload;
+
+                let note =
+                    Note::decrypt(payload.encrypted_note.as_ref(), ivk, &payload.ephemeral_key)
+                        .ok()?;
+                if note.commit() != payload.note_commitment
+                    || ivk.diversified_public(&note.diversified_generator())
+                        != note.transmission_key()
+                {
+                    return None;
+                }
+
+                let memo = MemoPlaintext::decrypt(
+                    output.body.encrypted_memo.clone(),
+                    ivk,
+                    &payload.ephemeral_key,
+                )
+                .ok()?;
+
+                Some(DecryptedOutput {
+                    action_index,
+                    note,
+                    memo,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Decrypt those outputs of the encoded transaction `tx_bytes` which pay addresses viewed by
+/// `ivk`.
+pub fn decrypt_transaction(
+    tx_bytes: &[u8],
+    ivk: &IncomingViewingKey,
+) -> anyhow::Result<Vec<DecryptedOutput>> {
+    Ok(Transaction::decode(tx_bytes)?.decrypt_outputs(ivk))
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        memo::MemoPlaintext,
+        transaction::Fee,
+        Note, Value, STAKING_TOKEN_ASSET_ID,
+    };
+    use penumbra_proto::Protobuf;
+    use penumbra_tct as tct;
+    use rand_core::OsRng;
+
+    use super::decrypt_transaction;
+    use crate::{
+        plan::{OutputPlan, SpendPlan, TransactionPlan},
+        WitnessData,
+    };
+
+    #[test]
+    fn decrypts_only_outputs_to_viewed_addresses() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (our_addr, _dtk) = fvk.incoming().payment_address(0u64.into());
+
+        let other_sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let (other_addr, _dtk) = other_sk
+            .full_viewing_key()
+            .incoming()
+            .payment_address(0u64.into());
+
+        let value = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let note = Note::generate(&mut OsRng, &our_addr, value(30));
+        let mut nct = tct::Tree::new();
+        nct.insert(tct::Witness::Keep, note.commit()).unwrap();
+
+        let memo = MemoPlaintext::try_from("rent payment".as_bytes()).unwrap();
+        let plan = TransactionPlan {
+            fee: Fee(0),
+            actions: vec![
+                SpendPlan::new(&mut OsRng, note, 0u64.into()).into(),
+                OutputPlan::new(&mut OsRng, value(20), other_addr, MemoPlaintext::default()).into(),
+                OutputPlan::new(&mut OsRng, value(10), our_addr, memo.clone()).into(),
+            ],
+            ..Default::default()
+        };
+
+        let auth_data = plan.authorize(OsRng, &sk);
+        let witness_data = WitnessData {
+            anchor: nct.root(),
+            note_commitment_proofs: plan
+                .spend_plans()
+                .map(|spend| nct.witness(spend.note.commit()).unwrap())
+                .collect(),
+        };
+        let transaction = plan
+            .build(&mut OsRng, fvk, auth_data, witness_data)
+            .unwrap();
+
+        let decrypted = decrypt_transaction(&transaction.encode_to_vec(), fvk.incoming()).unwrap();
+        assert_eq!(decrypted.len(), 1);
+        assert_eq!(decrypted[0].action_index, 2);
+        assert_eq!(decrypted[0].note.value(), value(10));
+        assert_eq!(decrypted[0].memo, memo);
+    }
+}
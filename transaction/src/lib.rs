@@ -2,6 +2,7 @@
 
 mod auth_data;
 mod auth_hash;
+mod decrypt;
 mod error;
 mod transaction;
 mod witness_data;
@@ -12,6 +13,7 @@ pub mod plan;
 pub use action::Action;
 pub use auth_data::AuthorizationData;
 pub use auth_hash::AuthHash;
+pub use decrypt::{decrypt_transaction, DecryptedOutput};
 pub use error::Error;
 pub use transaction::{Transaction, TransactionBody};
 pub use witness_data::WitnessData;
@@ -2,7 +2,7 @@
 //! creation.
 
 use anyhow::Result;
-use penumbra_crypto::transaction::Fee;
+use penumbra_crypto::{note, transaction::Fee, FullViewingKey, Nullifier, STAKING_TOKEN_ASSET_ID};
 use penumbra_proto::{ibc as pb_ibc, stake as pb_stake, transaction as pb, Protobuf};
 use serde::{Deserialize, Serialize};
 
@@ -13,8 +13,11 @@ use crate::action::{
 mod action;
 mod auth;
 mod build;
+mod summary;
 
 pub use action::{ActionPlan, DelegatorVotePlan, OutputPlan, SpendPlan};
+pub use auth::SpendAuthRequest;
+pub use summary::PlanSummary;
 
 /// A declaration of a planned [`Transaction`](crate::Transaction),
 /// for use in transaction authorization and creation.
@@ -60,6 +63,61 @@ impl TransactionPlan {
         })
     }
 
+    /// The commitments of the notes this plan sends to addresses viewed by `fvk`, such as change
+    /// outputs.
+    ///
+    /// These are known before the transaction is built, so a client can watch for them to be
+    /// scanned in order to track the transaction's confirmation.
+    pub fn change_commitments<'a>(
+        &'a self,
+        fvk: &'a FullViewingKey,
+    ) -> impl Iterator<Item = note::Commitment> + 'a {
+        self.output_plans()
+            .filter(move |output| fvk.incoming().views_address(&output.dest_address))
+            .map(|output| output.output_note().commit())
+    }
+
+    /// The nullifiers the spends in this plan will reveal.
+    ///
+    /// These are known before the transaction is built, so a client can treat the notes they
+    /// spend as pending while the transaction is in flight, rather than selecting them again.
+    pub fn nullifiers<'a>(
+        &'a self,
+        fvk: &'a FullViewingKey,
+    ) -> impl Iterator<Item = Nullifier> + 'a {
+        self.spend_plans()
+            .map(move |spend| fvk.derive_nullifier(spend.position, &spend.note.commit()))
+    }
+
+    /// The fee this plan actually pays: the staking token it spends but doesn't send to any
+    /// output, which includes any remainder left unallocated by its change outputs as well as the
+    /// requested [`fee`](Self::fee).
+    ///
+    /// Returns `None` if the plan has actions other than spends and outputs, since those move
+    /// value in ways not accounted for here, or if its outputs exceed its spends.
+    pub fn effective_fee(&self) -> Option<u64> {
+        let only_transfers = self
+            .actions
+            .iter()
+            .all(|action| matches!(action, ActionPlan::Spend(_) | ActionPlan::Output(_)));
+        if !only_transfers {
+            return None;
+        }
+
+        let spent = self
+            .spend_plans()
+            .map(|spend| spend.note.value())
+            .filter(|value| value.asset_id == *STAKING_TOKEN_ASSET_ID)
+            .map(|value| value.amount)
+            .sum::<u64>();
+        let output = self
+            .output_plans()
+            .filter(|output| output.value.asset_id == *STAKING_TOKEN_ASSET_ID)
+            .map(|output| output.value.amount)
+            .sum::<u64>();
+        spent.checked_sub(output)
+    }
+
     pub fn delegations(&self) -> impl Iterator<Item = &Delegate> {
         self.actions.iter().filter_map(|action| {
             if let ActionPlan::Delegate(d) = action {
@@ -172,3 +230,246 @@ impl TryFrom<pb::TransactionPlan> for TransactionPlan {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        memo::MemoPlaintext,
+        transaction::Fee,
+        Note, Value, STAKING_TOKEN_ASSET_ID,
+    };
+    use penumbra_proto::Protobuf;
+    use penumbra_tct as tct;
+    use rand::{rngs::StdRng, SeedableRng};
+    use rand_core::{CryptoRng, OsRng, RngCore};
+
+    use crate::{
+        plan::{OutputPlan, SpendPlan, TransactionPlan},
+        WitnessData,
+    };
+
+    /// An RNG counting the number of draws made from it, for checking that planning consumes
+    /// randomness in a fixed pattern.
+    pub struct CountingRng<R> {
+        inner: R,
+        pub draws: usize,
+    }
+
+    impl<R> CountingRng<R> {
+        pub fn new(inner: R) -> Self {
+            Self { inner, draws: 0 }
+        }
+    }
+
+    impl<R: RngCore> RngCore for CountingRng<R> {
+        fn next_u32(&mut self) -> u32 {
+            self.draws += 1;
+            self.inner.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.draws += 1;
+            self.inner.next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.draws += 1;
+            self.inner.fill_bytes(dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.draws += 1;
+            self.inner.try_fill_bytes(dest)
+        }
+    }
+
+    impl<R: CryptoRng> CryptoRng for CountingRng<R> {}
+
+    #[test]
+    fn change_commitments_match_scanned_change_notes() {
+        let mut rng = OsRng;
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let fvk = sk.full_viewing_key();
+        let (change_addr, _dtk) = fvk.incoming().payment_address(1u64.into());
+
+        let other_sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let (dest_addr, _dtk) = other_sk
+            .full_viewing_key()
+            .incoming()
+            .payment_address(0u64.into());
+
+        let mut nct = tct::Tree::new();
+        let note = Note::generate(
+            &mut OsRng,
+            &change_addr,
+            Value {
+                amount: 30000,
+                asset_id: *STAKING_TOKEN_ASSET_ID,
+            },
+        );
+        nct.insert(tct::Witness::Keep, note.commit()).unwrap();
+
+        let plan = TransactionPlan {
+            expiry_height: 0,
+            fee: Fee(0),
+            chain_id: "penumbra-test".to_string(),
+            actions: vec![
+                SpendPlan::new(&mut OsRng, note, 0u64.into()).into(),
+                OutputPlan::new(
+                    &mut OsRng,
+                    Value {
+                        amount: 10000,
+                        asset_id: *STAKING_TOKEN_ASSET_ID,
+                    },
+                    dest_addr,
+                    MemoPlaintext::default(),
+                )
+                .into(),
+                OutputPlan::new(
+                    &mut OsRng,
+                    Value {
+                        amount: 20000,
+                        asset_id: *STAKING_TOKEN_ASSET_ID,
+                    },
+                    change_addr,
+                    MemoPlaintext::default(),
+                )
+                .into(),
+            ],
+        };
+
+        let predicted = plan.change_commitments(fvk).collect::<Vec<_>>();
+        assert_eq!(predicted.len(), 1);
+        let predicted_nullifiers = plan.nullifiers(fvk).collect::<Vec<_>>();
+
+        let auth_data = plan.authorize(rng, &sk);
+        let witness_data = WitnessData {
+            anchor: nct.root(),
+            note_commitment_proofs: plan
+                .spend_plans()
+                .map(|spend| nct.witness(spend.note.commit()).unwrap())
+                .collect(),
+        };
+        let transaction = plan
+            .build(&mut OsRng, fvk, auth_data, witness_data)
+            .unwrap();
+
+        // Scanning the built transaction finds exactly the predicted change note.
+        let scanned = transaction
+            .note_payloads()
+            .filter_map(|payload| payload.trial_decrypt(fvk))
+            .map(|note| note.commit())
+            .collect::<Vec<_>>();
+        assert_eq!(scanned, predicted);
+        assert_eq!(
+            transaction.spent_nullifiers().collect::<Vec<_>>(),
+            predicted_nullifiers
+        );
+    }
+
+    #[test]
+    fn planning_consumes_randomness_reproducibly() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let (addr, _dtk) = sk
+            .full_viewing_key()
+            .incoming()
+            .payment_address(0u64.into());
+        let value = Value {
+            amount: 10000,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let note = Note::generate(&mut OsRng, &addr, value);
+
+        // Draw randomness for one spend followed by one output.
+        let plan_with = |rng: &mut CountingRng<StdRng>| TransactionPlan {
+            actions: vec![
+                SpendPlan::new(rng, note.clone(), 0u64.into()).into(),
+                OutputPlan::new(rng, value, addr, MemoPlaintext::default()).into(),
+            ],
+            ..Default::default()
+        };
+
+        let mut rng_a = CountingRng::new(StdRng::seed_from_u64(1));
+        let mut rng_b = CountingRng::new(StdRng::seed_from_u64(1));
+        let plan_a = plan_with(&mut rng_a);
+        let plan_b = plan_with(&mut rng_b);
+
+        assert!(rng_a.draws > 0);
+        assert_eq!(rng_a.draws, rng_b.draws);
+        assert_eq!(plan_a.encode_to_vec(), plan_b.encode_to_vec());
+    }
+
+    #[test]
+    fn authorize_with_external_signer_matches_spend_key() {
+        let mut rng = OsRng;
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let fvk = sk.full_viewing_key();
+        let (addr, _dtk) = fvk.incoming().payment_address(0u64.into());
+
+        let note = Note::generate(
+            &mut rng,
+            &addr,
+            Value {
+                amount: 10,
+                asset_id: *STAKING_TOKEN_ASSET_ID,
+            },
+        );
+        let plan = TransactionPlan {
+            actions: vec![SpendPlan::new(&mut rng, note, 0u64.into()).into()],
+            ..Default::default()
+        };
+
+        // A mock signer holding the spend key, as an HSM would.
+        let auth_data = plan
+            .authorize_with(fvk, |request| {
+                Ok(sk
+                    .spend_auth_key()
+                    .randomize(&request.randomizer)
+                    .sign(OsRng, request.auth_hash.as_ref()))
+            })
+            .unwrap();
+        assert_eq!(auth_data.spend_auths.len(), 1);
+
+        // A signer using the wrong key is rejected.
+        let other_sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        assert!(plan
+            .authorize_with(fvk, |request| {
+                Ok(other_sk
+                    .spend_auth_key()
+                    .randomize(&request.randomizer)
+                    .sign(OsRng, request.auth_hash.as_ref()))
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn effective_fee_includes_unallocated_remainder() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let (addr, _dtk) = sk
+            .full_viewing_key()
+            .incoming()
+            .payment_address(0u64.into());
+        let value = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let note = Note::generate(&mut OsRng, &addr, value(100));
+
+        // Spending 100 to send 60 with a fee of 10 leaves change of 30, but only 27 is returned.
+        let mut plan = TransactionPlan {
+            fee: Fee(10),
+            actions: vec![
+                SpendPlan::new(&mut OsRng, note, 0u64.into()).into(),
+                OutputPlan::new(&mut OsRng, value(60), addr, MemoPlaintext::default()).into(),
+                OutputPlan::new(&mut OsRng, value(27), addr, MemoPlaintext::change()).into(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(plan.effective_fee(), Some(13));
+
+        plan.actions
+            .push(OutputPlan::new(&mut OsRng, value(20), addr, MemoPlaintext::default()).into());
+        assert_eq!(plan.effective_fee(), None);
+    }
+}
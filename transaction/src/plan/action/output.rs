@@ -1,6 +1,6 @@
 use ark_ff::UniformRand;
 use penumbra_crypto::{
-    ka,
+    fmd, ka,
     keys::{IncomingViewingKey, OutgoingViewingKey},
     memo::MemoPlaintext,
     proofs::transparent::OutputProof,
@@ -52,9 +52,26 @@ impl OutputPlan {
         Output {
             body: self.output_body(ovk),
             proof: self.output_proof(),
+            #[cfg(feature = "fmd-clues")]
+            clue: None,
         }
     }
 
+    /// Construct the [`Output`] described by this plan, carrying a [`clue`](OutputPlan::clue)
+    /// for its destination address.
+    #[cfg(feature = "fmd-clues")]
+    pub fn output_with_clue<R: RngCore + CryptoRng>(
+        &self,
+        ovk: &OutgoingViewingKey,
+        precision_bits: usize,
+        rng: R,
+    ) -> anyhow::Result<Output> {
+        Ok(Output {
+            clue: Some(self.clue(precision_bits, rng)?),
+            ..self.output(ovk)
+        })
+    }
+
     pub fn output_note(&self) -> Note {
         let diversifier = self.dest_address.diversifier().clone();
         let transmission_key = self.dest_address.transmission_key().clone();
@@ -67,6 +84,22 @@ impl OutputPlan {
         .expect("transmission key in address is always valid")
     }
 
+    /// Create a fuzzy message detection clue for the destination address of this output.
+    ///
+    /// The clue is matched by the recipient's detection key, and by any other detection key with
+    /// probability `2^{-precision_bits}`.
+    pub fn clue<R: RngCore + CryptoRng>(
+        &self,
+        precision_bits: usize,
+        rng: R,
+    ) -> anyhow::Result<fmd::Clue> {
+        Ok(self
+            .dest_address
+            .clue_key()
+            .expand()?
+            .create_clue(precision_bits, rng)?)
+    }
+
     /// Construct the [`OutputProof`] required by the [`output::Body`] described
     /// by this plan.
     pub fn output_proof(&self) -> OutputProof {
@@ -150,3 +183,38 @@ impl TryFrom<pb::OutputPlan> for OutputPlan {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        memo::MemoPlaintext,
+        Value, STAKING_TOKEN_ASSET_ID,
+    };
+    use rand_core::OsRng;
+
+    use super::OutputPlan;
+
+    #[test]
+    fn clue_is_detected_by_recipient() {
+        let mut rng = OsRng;
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let (dest_address, dtk) = sk
+            .full_viewing_key()
+            .incoming()
+            .payment_address(0u64.into());
+
+        let plan = OutputPlan::new(
+            &mut rng,
+            Value {
+                amount: 10,
+                asset_id: *STAKING_TOKEN_ASSET_ID,
+            },
+            dest_address,
+            MemoPlaintext::default(),
+        );
+
+        let clue = plan.clue(8, &mut rng).unwrap();
+        assert!(dtk.examine(&clue));
+    }
+}
@@ -1,7 +1,20 @@
-use penumbra_crypto::keys::SpendKey;
+use anyhow::Context;
+use penumbra_crypto::{
+    keys::SpendKey,
+    rdsa::{Signature, SpendAuth},
+    Fr, FullViewingKey,
+};
 use rand::{CryptoRng, RngCore};
 
-use crate::{plan::TransactionPlan, AuthorizationData};
+use crate::{plan::TransactionPlan, AuthHash, AuthorizationData};
+
+/// A request to sign a transaction's authorization hash with the spend authorization key,
+/// randomized by `randomizer`, on behalf of one of its spends.
+#[derive(Clone, Debug)]
+pub struct SpendAuthRequest {
+    pub auth_hash: AuthHash,
+    pub randomizer: Fr,
+}
 
 impl TransactionPlan {
     /// Authorize this [`TransactionPlan`] with the provided [`SpendKey`].
@@ -24,4 +37,36 @@ impl TransactionPlan {
             spend_auths,
         }
     }
+
+    /// Authorize this [`TransactionPlan`] using an external signer, such as an HSM, so that the
+    /// spend key never needs to be in memory.
+    ///
+    /// `sign` is called once for each spend, in order, and each returned signature is checked
+    /// against the spend's randomized verification key.
+    pub fn authorize_with<F>(
+        &self,
+        fvk: &FullViewingKey,
+        mut sign: F,
+    ) -> anyhow::Result<AuthorizationData>
+    where
+        F: FnMut(SpendAuthRequest) -> anyhow::Result<Signature<SpendAuth>>,
+    {
+        let auth_hash = self.auth_hash(fvk);
+        let mut spend_auths = Vec::new();
+        for spend_plan in self.spend_plans() {
+            let auth_sig = sign(SpendAuthRequest {
+                auth_hash,
+                randomizer: spend_plan.randomizer,
+            })?;
+            fvk.spend_verification_key()
+                .randomize(&spend_plan.randomizer)
+                .verify(auth_hash.as_ref(), &auth_sig)
+                .context("external signer produced an invalid spend auth signature")?;
+            spend_auths.push(auth_sig);
+        }
+        Ok(AuthorizationData {
+            auth_hash,
+            spend_auths,
+        })
+    }
 }
@@ -5,6 +5,11 @@ use rand_core::{CryptoRng, RngCore};
 use super::TransactionPlan;
 use crate::{action::Action, AuthorizationData, Transaction, TransactionBody, WitnessData};
 
+/// The precision of the clues attached to built outputs: a detection key other than the
+/// recipient's matches a clue with probability `2^-CLUE_PRECISION_BITS`.
+#[cfg(feature = "fmd-clues")]
+const CLUE_PRECISION_BITS: usize = 4;
+
 impl TransactionPlan {
     /// Build the transaction this plan describes.
     ///
@@ -61,7 +66,12 @@ impl TransactionPlan {
         for output_plan in self.output_plans() {
             // Outputs subtract from the transaction's value balance.
             synthetic_blinding_factor -= output_plan.value_blinding;
-            actions.push(Action::Output(output_plan.output(fvk.outgoing())));
+            #[cfg(feature = "fmd-clues")]
+            let output =
+                output_plan.output_with_clue(fvk.outgoing(), CLUE_PRECISION_BITS, &mut *rng)?;
+            #[cfg(not(feature = "fmd-clues"))]
+            let output = output_plan.output(fvk.outgoing());
+            actions.push(Action::Output(output));
         }
 
         // We don't have anything more to build, but iterate through the rest of
@@ -103,3 +113,55 @@ impl TransactionPlan {
         })
     }
 }
+
+#[cfg(all(test, feature = "fmd-clues"))]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        memo::MemoPlaintext,
+        Value, STAKING_TOKEN_ASSET_ID,
+    };
+    use penumbra_tct as tct;
+    use rand_core::OsRng;
+
+    use crate::{
+        action::Action,
+        plan::{ActionPlan, OutputPlan, TransactionPlan},
+        AuthorizationData, WitnessData,
+    };
+
+    #[test]
+    fn built_output_carries_clue_for_recipient() {
+        let mut rng = OsRng;
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let fvk = sk.full_viewing_key();
+        let (dest_address, dtk) = fvk.incoming().payment_address(0u64.into());
+
+        let plan = TransactionPlan {
+            actions: vec![ActionPlan::Output(OutputPlan::new(
+                &mut rng,
+                Value {
+                    amount: 10,
+                    asset_id: *STAKING_TOKEN_ASSET_ID,
+                },
+                dest_address,
+                MemoPlaintext::default(),
+            ))],
+            ..Default::default()
+        };
+        let auth_data = AuthorizationData {
+            auth_hash: plan.auth_hash(fvk),
+            spend_auths: Vec::new(),
+        };
+        let witness_data = WitnessData {
+            anchor: tct::Tree::new().root(),
+            note_commitment_proofs: Vec::new(),
+        };
+        let transaction = plan.build(&mut rng, fvk, auth_data, witness_data).unwrap();
+
+        match &transaction.transaction_body.actions[..] {
+            [Action::Output(output)] => assert!(dtk.examine(output.clue.as_ref().unwrap())),
+            actions => panic!("expected a single output, got {:?}", actions),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use penumbra_crypto::{asset, FullViewingKey, Value, STAKING_TOKEN_ASSET_ID};
+
+use crate::plan::TransactionPlan;
+
+/// A compact, human-verifiable summary of a [`TransactionPlan`], for display on a signing device
+/// with a small screen, so the user can check what they are authorizing.
+///
+/// The summary is derived deterministically from the plan, so a device and its host agree on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlanSummary {
+    /// The payments made to addresses not controlled by the signer, as pairs of the recipient
+    /// address and the value sent, in display units.
+    pub payments: Vec<(String, String)>,
+    /// The fee paid, in display units.
+    pub fee: String,
+}
+
+impl TransactionPlan {
+    /// Summarize the payments and fee of this plan, formatting values with the denominations in
+    /// `cache`. Outputs to addresses viewed by `fvk`, such as change, are omitted.
+    pub fn summary(&self, fvk: &FullViewingKey, cache: &asset::Cache) -> PlanSummary {
+        let payments = self
+            .output_plans()
+            .filter(|output| !fvk.incoming().views_address(&output.dest_address))
+            .map(|output| (output.dest_address.to_string(), output.value.format(cache)))
+            .collect();
+
+        let fee = Value {
+            amount: self.fee.0,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        }
+        .format(cache);
+
+        PlanSummary { payments, fee }
+    }
+}
+
+impl std::fmt::Display for PlanSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (address, value) in &self.payments {
+            writeln!(f, "Send {} to {}", value, address)?;
+        }
+        write!(f, "Fee: {}", self.fee)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        asset,
+        keys::{SeedPhrase, SpendKey},
+        memo::MemoPlaintext,
+        transaction::Fee,
+        Value, STAKING_TOKEN_ASSET_ID, STAKING_TOKEN_DENOM,
+    };
+    use rand_core::OsRng;
+
+    use crate::plan::{OutputPlan, TransactionPlan};
+
+    #[test]
+    fn summary_shows_payments_in_display_units() {
+        let mut rng = OsRng;
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let fvk = sk.full_viewing_key();
+        let (change_addr, _dtk) = fvk.incoming().payment_address(0u64.into());
+        let other_sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut rng), 0);
+        let (dest_addr, _dtk) = other_sk
+            .full_viewing_key()
+            .incoming()
+            .payment_address(0u64.into());
+
+        let output = |amount, address| {
+            OutputPlan::new(
+                &mut OsRng,
+                Value {
+                    amount,
+                    asset_id: *STAKING_TOKEN_ASSET_ID,
+                },
+                address,
+                MemoPlaintext::default(),
+            )
+            .into()
+        };
+        let plan = TransactionPlan {
+            fee: Fee(10_000),
+            actions: vec![output(1_500_000, dest_addr), output(2_000_000, change_addr)],
+            ..Default::default()
+        };
+
+        let cache = [STAKING_TOKEN_DENOM.clone()]
+            .into_iter()
+            .collect::<asset::Cache>();
+        let summary = plan.summary(fvk, &cache);
+
+        assert_eq!(
+            summary.payments,
+            vec![(dest_addr.to_string(), "1.5penumbra".to_string())]
+        );
+        assert_eq!(summary.fee, "10mpenumbra");
+    }
+}
@@ -0,0 +1,7 @@
+-- Nullifiers for notes we had already recorded as spent, which indicate a serious
+-- inconsistency (e.g. a block being scanned twice), set aside for manual review
+CREATE TABLE inconsistent_nullifiers (
+    nullifier               BLOB PRIMARY KEY NOT NULL,
+    note_commitment         BLOB NOT NULL,
+    height                  BIGINT NOT NULL
+);
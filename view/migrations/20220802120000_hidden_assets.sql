@@ -0,0 +1,4 @@
+-- Assets the user has hidden (e.g. spam airdrops), which are excluded from balance views
+CREATE TABLE hidden_assets (
+    asset_id BLOB PRIMARY KEY NOT NULL
+);
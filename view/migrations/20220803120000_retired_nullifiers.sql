@@ -0,0 +1,4 @@
+-- Nullifiers of spent notes which were pruned from the notes table.
+CREATE TABLE retired_nullifiers (
+    nullifier               BLOB PRIMARY KEY NOT NULL
+);
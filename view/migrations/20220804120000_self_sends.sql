@@ -0,0 +1,5 @@
+-- Transactions detected while scanning as paying only ourselves, by height.
+CREATE TABLE self_sends (
+    tx_hash                 BLOB PRIMARY KEY NOT NULL,
+    height                  BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Assets whose notes are not selected for spending, though they still count towards balances.
+CREATE TABLE non_spendable_assets (
+    asset_id                BLOB PRIMARY KEY NOT NULL
+);
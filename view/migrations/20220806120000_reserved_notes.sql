@@ -0,0 +1,4 @@
+-- Notes reserved for an in-progress send, which are not returned as unspent until released.
+CREATE TABLE reserved_notes (
+    note_commitment         BLOB PRIMARY KEY NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Transactions we've submitted but not yet seen in a block, with the height after which they
+-- can no longer be included.
+CREATE TABLE pending_transactions (
+    tx_hash                 BLOB PRIMARY KEY NOT NULL,
+    expiry_height           BIGINT NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Our notes spent by transactions we've submitted but not yet seen in a block, which are not
+-- returned as unspent until the transaction is confirmed or fails.
+CREATE TABLE pending_spends (
+    note_commitment         BLOB PRIMARY KEY NOT NULL,
+    tx_hash                 BLOB NOT NULL
+);
+
+-- Change we expect to receive from transactions we've submitted but not yet seen in a block.
+CREATE TABLE pending_change (
+    note_commitment         BLOB PRIMARY KEY NOT NULL,
+    tx_hash                 BLOB NOT NULL,
+    asset_id                BLOB NOT NULL,
+    amount                  BIGINT NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Spent notes whose plaintext was dropped, keeping only what's needed to account for their value.
+CREATE TABLE spent_note_tombstones (
+    note_commitment         BLOB PRIMARY KEY NOT NULL,
+    nullifier               BLOB NOT NULL,
+    amount                  BIGINT NOT NULL,
+    asset_id                BLOB NOT NULL,
+    height_created          BIGINT NOT NULL,
+    height_spent            BIGINT NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Our notes which we had submitted a transaction to spend, but which were spent by a different
+-- transaction, which may indicate that the spending key is also held by someone else
+CREATE TABLE foreign_spends (
+    note_commitment         BLOB PRIMARY KEY NOT NULL,
+    nullifier               BLOB NOT NULL,
+    tx_hash                 BLOB NOT NULL,
+    height                  BIGINT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Application-specific settings (e.g. a display currency or address labels) stored by clients
+-- alongside the wallet state, as JSON values which are opaque to the view service
+CREATE TABLE metadata (
+    key                     TEXT PRIMARY KEY NOT NULL,
+    value                   TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- The height after which a reservation lapses if its notes haven't been spent, or 0 if it never
+-- lapses.
+ALTER TABLE reserved_notes ADD COLUMN expiry_height BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Assets of spent notes which were pruned from the notes table, so they remain known.
+CREATE TABLE pruned_note_assets (
+    asset_id                BLOB PRIMARY KEY NOT NULL
+);
@@ -51,6 +51,9 @@ enum Command {
         /// Bind the view gRPC server to this port.
         #[clap(long, default_value = "8081")]
         view_port: u16,
+        /// Check the stored nullifiers against the full viewing key before starting.
+        #[clap(long)]
+        verify: bool,
     },
 }
 #[tokio::main]
@@ -81,10 +84,18 @@ async fn main() -> Result<()> {
             .await?;
             Ok(())
         }
-        Command::Start { host, view_port } => {
+        Command::Start {
+            host,
+            view_port,
+            verify,
+        } => {
             tracing::info!(?opt.sqlite_path, ?host, ?view_port, ?opt.node, ?opt.tendermint_port, ?opt.pd_port, "starting pviewd");
 
-            let storage = penumbra_view::Storage::load(opt.sqlite_path).await?;
+            let storage = if verify {
+                penumbra_view::Storage::load_verified(opt.sqlite_path).await?
+            } else {
+                penumbra_view::Storage::load(opt.sqlite_path).await?
+            };
 
             let service =
                 ViewService::new(storage, opt.node, opt.pd_port, opt.tendermint_port).await?;
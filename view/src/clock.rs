@@ -0,0 +1,74 @@
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+
+/// A source of wall-clock time.
+///
+/// The view service reads the current time through this trait, rather than
+/// calling [`SystemTime::now`] directly, so that time-dependent behavior (such
+/// as the last sync timestamp) can be tested deterministically.
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// The current wall-clock time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The default [`Clock`], which reads the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A [`Clock`] which only moves when told to, for use in tests.
+///
+/// Clones of a [`MockClock`] share the same time.
+#[derive(Clone, Debug)]
+pub struct MockClock(Arc<Mutex<SystemTime>>);
+
+impl MockClock {
+    /// Create a new [`MockClock`] stopped at `time`.
+    pub fn new(time: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(time)))
+    }
+
+    /// Set the time reported by this clock.
+    pub fn set(&self, time: SystemTime) {
+        *self.0.lock() = time;
+    }
+
+    /// Move the time reported by this clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_is_deterministic() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let clock = MockClock::new(start);
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+
+        assert_eq!(shared.now(), start);
+        assert_eq!(shared.now(), start);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(shared.now(), start + Duration::from_secs(5));
+    }
+}
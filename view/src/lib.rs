@@ -2,21 +2,46 @@
 #![recursion_limit = "256"]
 
 mod client;
+mod clock;
 mod metrics;
+mod note_backup;
 mod note_record;
+mod payment_receipt;
 mod quarantined_note_record;
+mod send_session;
 mod service;
+mod spend_policy;
+mod spent_note;
 mod status;
 mod storage;
 mod sync;
+#[cfg(test)]
+mod test_utils;
+mod transaction_record;
+mod transaction_state;
 mod worker;
 
 use worker::Worker;
 
 pub use crate::metrics::register_metrics;
 pub use client::ViewClient;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use note_backup::NoteBackup;
 pub use note_record::NoteRecord;
+pub use payment_receipt::{PaymentReceipt, PaymentRequest};
 pub use quarantined_note_record::QuarantinedNoteRecord;
+pub use send_session::SendSession;
 pub use service::ViewService;
+pub use spend_policy::SpendPolicy;
+pub use spent_note::SpentNoteTombstone;
 pub use status::StatusStreamResponse;
-pub use storage::Storage;
+pub use storage::{
+    AnchorMismatch, AssetNonSpendable, Storage, UnexpectedBlockHeight, DEFAULT_CHECKPOINT_INTERVAL,
+    MAX_MERKLE_CHECKPOINTS_CLIENT, MAX_METADATA_BYTES, MAX_SCAN_HISTORY, RESERVATION_EXPIRY_BLOCKS,
+};
+pub use sync::{
+    missing_relevant_heights, scan_block, scan_blocks, scan_filtered_block, unknown_asset_ids,
+    BalanceProgress, BlockScanResult, FilteredBlock, ScanStats, TreeFull,
+};
+pub use transaction_record::TransactionRecord;
+pub use transaction_state::TransactionState;
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Context};
+use penumbra_crypto::asset;
+use penumbra_proto::Protobuf;
+use penumbra_tct as tct;
+use serde::{Deserialize, Serialize};
+
+use crate::NoteRecord;
+
+/// A backup of some of our notes, together with the proofs needed to witness them for spending.
+///
+/// The proofs are all generated against the same anchor, so restoring the backup doesn't need the
+/// note commitment tree; the notes can be spent using these proofs for as long as that anchor is
+/// accepted by the chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteBackup {
+    anchor: tct::Root,
+    notes: Vec<BackedUpNote>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackedUpNote {
+    record: NoteRecord,
+    #[serde(with = "penumbra_proto::serializers::hexstr")]
+    proof: Vec<u8>,
+}
+
+impl NoteBackup {
+    /// Back up those of the given notes which are of the asset `asset_id`, witnessing each in
+    /// `nct`.
+    ///
+    /// This fails if any of those notes is not witnessed in `nct`, e.g. because it was spent.
+    pub fn for_asset(
+        nct: &tct::Tree,
+        records: impl IntoIterator<Item = NoteRecord>,
+        asset_id: asset::Id,
+    ) -> anyhow::Result<Self> {
+        let notes = records
+            .into_iter()
+            .filter(|record| record.note.asset_id() == asset_id)
+            .map(|record| {
+                let proof = nct
+                    .witness(record.note_commitment)
+                    .ok_or_else(|| anyhow!("note {} is not witnessed", record.note_commitment))?;
+                Ok(BackedUpNote {
+                    record,
+                    proof: proof.encode_to_vec(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            anchor: nct.root(),
+            notes,
+        })
+    }
+
+    /// The anchor the backed up notes' proofs were generated against.
+    pub fn anchor(&self) -> tct::Root {
+        self.anchor
+    }
+
+    /// Restore the backed up notes, checking that each proof matches its note's commitment and
+    /// position and verifies against the backup's anchor.
+    pub fn restore(&self) -> anyhow::Result<Vec<(NoteRecord, tct::Proof)>> {
+        self.notes
+            .iter()
+            .map(|backed_up| {
+                let record = &backed_up.record;
+                let proof = tct::Proof::decode(backed_up.proof.as_slice()).with_context(|| {
+                    format!("invalid proof for note {}", record.note_commitment)
+                })?;
+
+                if proof.commitment() != record.note_commitment
+                    || proof.position() != record.position
+                {
+                    return Err(anyhow!(
+                        "proof does not match note {}",
+                        record.note_commitment
+                    ));
+                }
+                proof.verify(self.anchor).with_context(|| {
+                    format!("proof for note {} is invalid", record.note_commitment)
+                })?;
+
+                Ok((record.clone(), proof))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        asset,
+        keys::{SeedPhrase, SpendKey},
+        STAKING_TOKEN_ASSET_ID,
+    };
+    use penumbra_tct as tct;
+    use rand_core::OsRng;
+
+    use super::NoteBackup;
+    use crate::test_utils::note_record;
+
+    #[test]
+    fn asset_backup_restores_only_that_asset() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let other_asset_id = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+
+        let mut nct = tct::Tree::new();
+        let records = [
+            *STAKING_TOKEN_ASSET_ID,
+            other_asset_id,
+            *STAKING_TOKEN_ASSET_ID,
+        ]
+        .into_iter()
+        .map(|asset_id| {
+            let position = nct.position().unwrap();
+            let record = note_record(fvk, 0, 1, asset_id, 1, position.into());
+            nct.insert(tct::Witness::Keep, record.note_commitment)
+                .unwrap();
+            record
+        })
+        .collect::<Vec<_>>();
+
+        let backup = NoteBackup::for_asset(&nct, records, *STAKING_TOKEN_ASSET_ID).unwrap();
+        let backup: NoteBackup =
+            serde_json::from_str(&serde_json::to_string(&backup).unwrap()).unwrap();
+
+        let restored = backup.restore().unwrap();
+        assert_eq!(restored.len(), 2);
+        for (record, proof) in restored {
+            assert_eq!(record.note.asset_id(), *STAKING_TOKEN_ASSET_ID);
+            assert!(proof.verify(nct.root()).is_ok());
+        }
+    }
+}
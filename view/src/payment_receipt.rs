@@ -0,0 +1,99 @@
+use anyhow::anyhow;
+use penumbra_chain::NoteSource;
+use penumbra_crypto::{asset, keys::AddressIndex, Address, Note, Value};
+use penumbra_tct as tct;
+
+use crate::NoteRecord;
+
+/// Evidence that a note paying some value to one of our addresses was included in the chain.
+///
+/// A receipt contains the note plaintext, which opens the note's commitment, together with a proof
+/// of that commitment's inclusion in the note commitment tree. A third party who trusts an anchor
+/// can use it to confirm which address the note pays and its value, without learning any key
+/// material.
+///
+/// Receipts can only be produced while the note is still witnessed, i.e., while it is unspent.
+#[derive(Debug, Clone)]
+pub struct PaymentReceipt {
+    /// The note plaintext.
+    pub note: Note,
+    /// Where the note came from (e.g. the hash of the transaction which created it).
+    pub source: NoteSource,
+    /// The anchor the inclusion proof was generated against.
+    pub anchor: tct::Root,
+    /// The proof of inclusion of the note's commitment.
+    pub proof: tct::Proof,
+}
+
+impl PaymentReceipt {
+    /// Verify that this receipt proves a payment to `address` included in the tree with root
+    /// `anchor`, returning the value paid.
+    pub fn verify(&self, address: &Address, anchor: tct::Root) -> anyhow::Result<Value> {
+        if self.proof.commitment() != self.note.commit() {
+            return Err(anyhow!("note does not open the proven commitment"));
+        }
+
+        self.proof.verify(anchor)?;
+
+        if self.note.diversifier() != *address.diversifier()
+            || self.note.transmission_key() != *address.transmission_key()
+        {
+            return Err(anyhow!("note was not paid to the given address"));
+        }
+
+        Ok(self.note.value())
+    }
+}
+
+/// A request for payment of at least `amount` of `asset_id` to the address with the given index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaymentRequest {
+    pub address_index: AddressIndex,
+    pub asset_id: asset::Id,
+    pub amount: u64,
+}
+
+impl PaymentRequest {
+    /// Whether the received note satisfies this request.
+    pub fn is_satisfied_by(&self, record: &NoteRecord) -> bool {
+        record.address_index == self.address_index
+            && record.note.asset_id() == self.asset_id
+            && record.note.amount() >= self.amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        STAKING_TOKEN_ASSET_ID,
+    };
+    use rand_core::OsRng;
+
+    use super::PaymentRequest;
+    use crate::test_utils::note_record;
+
+    #[test]
+    fn payment_request_matches_received_note() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let record = note_record(sk.full_viewing_key(), 3, 100, *STAKING_TOKEN_ASSET_ID, 1, 0);
+
+        let request = PaymentRequest {
+            address_index: 3u64.into(),
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+            amount: 100,
+        };
+        assert!(request.is_satisfied_by(&record));
+
+        let wrong_address = PaymentRequest {
+            address_index: 4u64.into(),
+            ..request
+        };
+        let too_much = PaymentRequest {
+            amount: 101,
+            ..request
+        };
+        assert!(!wrong_address.is_satisfied_by(&record));
+        assert!(!too_much.is_satisfied_by(&record));
+    }
+}
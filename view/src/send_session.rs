@@ -0,0 +1,176 @@
+use penumbra_crypto::asset;
+
+use crate::{NoteRecord, Storage};
+
+/// A send in progress, holding a reservation on the notes selected for it.
+///
+/// While reserved, the notes are excluded from the unspent notes returned by [`Storage::notes`],
+/// so they count towards neither balances nor other note selections. The reservation is released
+/// by [`SendSession::cancel`], or kept by [`SendSession::finalize`] until the notes' spend is
+/// scanned. A reservation whose notes aren't spent lapses after
+/// [`RESERVATION_EXPIRY_BLOCKS`](crate::RESERVATION_EXPIRY_BLOCKS) blocks.
+pub struct SendSession {
+    storage: Storage,
+    notes: Vec<NoteRecord>,
+}
+
+impl SendSession {
+    /// Select and reserve notes of `asset_id` covering at least `amount`, failing if another
+    /// session reserves any of them concurrently.
+    pub(crate) async fn begin(
+        storage: Storage,
+        asset_id: asset::Id,
+        amount: u64,
+    ) -> anyhow::Result<Self> {
+        let notes = storage.reserve_spendable_notes(asset_id, amount).await?;
+
+        Ok(Self { storage, notes })
+    }
+
+    /// The notes reserved for this send.
+    pub fn notes(&self) -> &[NoteRecord] {
+        &self.notes
+    }
+
+    /// Complete the send, returning the reserved notes to spend. They remain reserved, so they
+    /// can't be selected again before the spend is scanned.
+    pub fn finalize(self) -> Vec<NoteRecord> {
+        self.notes
+    }
+
+    /// Abandon the send, releasing the reserved notes.
+    pub async fn cancel(self) -> anyhow::Result<()> {
+        self.storage
+            .release_notes(self.notes.iter().map(|record| record.note_commitment))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        STAKING_TOKEN_ASSET_ID,
+    };
+    use penumbra_tct as tct;
+    use rand_core::OsRng;
+
+    use crate::{
+        test_utils::{filtered_block, note_record, record_notes, temp_storage},
+        RESERVATION_EXPIRY_BLOCKS,
+    };
+
+    #[tokio::test]
+    async fn cancelling_restores_availability() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 0, 0)],
+        )
+        .await;
+        let unspent = || storage.notes(false, None, None, 0, true);
+
+        let session = storage
+            .begin_send(*STAKING_TOKEN_ASSET_ID, 5)
+            .await
+            .unwrap();
+        assert_eq!(session.notes().len(), 1);
+        assert!(unspent().await.unwrap().is_empty());
+        assert!(storage
+            .begin_send(*STAKING_TOKEN_ASSET_ID, 5)
+            .await
+            .is_err());
+
+        session.cancel().await.unwrap();
+        assert_eq!(unspent().await.unwrap().len(), 1);
+        assert!(storage.reserved_notes().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_sessions_cannot_reserve_the_same_notes() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 0, 0)],
+        )
+        .await;
+
+        let (first, second) = tokio::join!(
+            storage.begin_send(*STAKING_TOKEN_ASSET_ID, 5),
+            storage.begin_send(*STAKING_TOKEN_ASSET_ID, 5),
+        );
+        assert_eq!(
+            [first.is_ok(), second.is_ok()]
+                .into_iter()
+                .filter(|ok| *ok)
+                .count(),
+            1
+        );
+        assert_eq!(storage.reserved_notes().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reservations_end_when_spent_or_expired() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let spent = note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 0, 0);
+        let abandoned = note_record(fvk, 0, 20, *STAKING_TOKEN_ASSET_ID, 0, 1);
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![spent.clone(), abandoned.clone()],
+        )
+        .await;
+
+        // Finalize a send of both notes, of which only the first one's spend is ever scanned.
+        let session = storage
+            .begin_send(*STAKING_TOKEN_ASSET_ID, 30)
+            .await
+            .unwrap();
+        assert_eq!(session.finalize().len(), 2);
+
+        storage
+            .record_block(
+                filtered_block(1, Vec::new(), vec![spent.nullifier]),
+                Vec::new(),
+                &mut nct,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.reserved_notes().await.unwrap(),
+            vec![abandoned.note_commitment]
+        );
+
+        // The other note's reservation lapses once its expiry height has passed.
+        for height in 2..=RESERVATION_EXPIRY_BLOCKS {
+            storage.record_empty_block(height).await.unwrap();
+        }
+        storage
+            .record_block(
+                filtered_block(RESERVATION_EXPIRY_BLOCKS + 1, Vec::new(), Vec::new()),
+                Vec::new(),
+                &mut nct,
+            )
+            .await
+            .unwrap();
+        assert!(storage.reserved_notes().await.unwrap().is_empty());
+        assert_eq!(
+            storage.notes(false, None, None, 0, true).await.unwrap()[0].note_commitment,
+            abandoned.note_commitment
+        );
+    }
+}
@@ -10,6 +10,7 @@ use futures::stream::{StreamExt, TryStreamExt};
 use penumbra_crypto::{
     asset,
     keys::{AddressIndex, FullViewingKey, FullViewingKeyHash},
+    note,
 };
 use penumbra_proto::{
     chain as pbp,
@@ -24,7 +25,7 @@ use tokio_stream::wrappers::WatchStream;
 use tonic::async_trait;
 use tracing::instrument;
 
-use crate::{Storage, Worker};
+use crate::{AssetNonSpendable, NoteBackup, PaymentReceipt, Storage, Worker};
 
 /// A service that synchronizes private chain state and responds to queries
 /// about it.
@@ -59,8 +60,10 @@ impl ViewService {
         node: String,
         pd_port: u16,
         tendermint_port: u16,
+        verify: bool,
     ) -> anyhow::Result<Self> {
-        let storage = Storage::load_or_initialize(storage_path, fvk, node.clone(), pd_port).await?;
+        let storage =
+            Storage::load_or_initialize(storage_path, fvk, node.clone(), pd_port, verify).await?;
 
         Self::new(storage, node, pd_port, tendermint_port).await
     }
@@ -191,6 +194,84 @@ impl ViewService {
         Ok((latest_known_block_height, node_catching_up))
     }
 
+    /// Return the commitments of unspent notes which can't currently be witnessed, and so can't
+    /// be spent until they are re-witnessed by rescanning (e.g. after a rollback).
+    #[instrument(skip(self))]
+    pub async fn notes_missing_witness(&self) -> Result<Vec<note::Commitment>, anyhow::Error> {
+        let notes = self.storage.notes(false, None, None, 0, true).await?;
+
+        // Hold the read lock while checking all notes, so they're checked against the same NCT.
+        let nct = self.note_commitment_tree.read().await;
+
+        Ok(notes
+            .into_iter()
+            .map(|record| record.note_commitment)
+            .filter(|note_commitment| nct.witness(*note_commitment).is_none())
+            .collect())
+    }
+
+    /// Produce a [`PaymentReceipt`] proving receipt of the note with the given commitment, if it is
+    /// one of our unspent notes.
+    #[instrument(skip(self))]
+    pub async fn payment_receipt(
+        &self,
+        note_commitment: note::Commitment,
+    ) -> Result<Option<PaymentReceipt>, anyhow::Error> {
+        let (anchor, proof) = {
+            let nct = self.note_commitment_tree.read().await;
+            match nct.witness(note_commitment) {
+                Some(proof) => (nct.root(), proof),
+                None => return Ok(None),
+            }
+        };
+
+        let record = self
+            .storage
+            .note_by_commitment(note_commitment, false)
+            .await?;
+
+        Ok(Some(PaymentReceipt {
+            note: record.note,
+            source: record.source,
+            anchor,
+            proof,
+        }))
+    }
+
+    /// The current root of our note commitment tree, usable as the anchor of a transaction built
+    /// outside of the view service.
+    #[instrument(skip(self))]
+    pub async fn anchor(&self) -> penumbra_tct::Root {
+        self.note_commitment_tree.read().await.root()
+    }
+
+    /// The number of commitments appended to our note commitment tree so far, including those
+    /// which were not witnessed.
+    #[instrument(skip(self))]
+    pub async fn note_commitment_tree_size(&self) -> u64 {
+        self.note_commitment_tree
+            .read()
+            .await
+            .position()
+            // A full tree has no next position, and holds the maximum number of commitments.
+            .map_or(1 << 48, u64::from)
+    }
+
+    /// Back up our unspent notes of the given asset, with the proofs needed to spend them.
+    #[instrument(skip(self))]
+    pub async fn export_notes_for_asset(
+        &self,
+        asset_id: asset::Id,
+    ) -> Result<NoteBackup, anyhow::Error> {
+        let records = self
+            .storage
+            .notes(false, Some(asset_id), None, 0, true)
+            .await?;
+
+        let nct = self.note_commitment_tree.read().await;
+        NoteBackup::for_asset(&nct, records, asset_id)
+    }
+
     #[instrument(skip(self))]
     pub async fn status(&self) -> Result<StatusResponse, anyhow::Error> {
         let sync_height = self.storage.last_sync_height().await?.unwrap_or(0);
@@ -352,12 +433,35 @@ impl ViewProtocol for ViewService {
             .map_or(Ok(None), |v| v.map(Some))
             .map_err(|_| tonic::Status::invalid_argument("invalid address index"))?;
         let amount_to_spend = request.get_ref().amount_to_spend;
-
-        let notes = self
-            .storage
-            .notes(include_spent, asset_id, address_index, amount_to_spend)
-            .await
-            .map_err(|e| tonic::Status::unavailable(format!("error fetching notes: {}", e)))?;
+        // Notes of hidden assets are only returned when explicitly requested by asset id, so that
+        // they are excluded from balance views but can still be spent.
+        let include_hidden = asset_id.is_some();
+
+        // Requests for the unspent notes of an asset select notes for spending, whether or not
+        // they give an amount to spend (the planner selects among all of them itself), and
+        // selecting notes of non-spendable assets is refused.
+        let notes = match (include_spent, asset_id) {
+            (false, Some(asset_id)) => {
+                self.storage
+                    .spendable_notes(asset_id, address_index, amount_to_spend)
+                    .await
+            }
+            _ => {
+                self.storage
+                    .notes(
+                        include_spent,
+                        asset_id,
+                        address_index,
+                        amount_to_spend,
+                        include_hidden,
+                    )
+                    .await
+            }
+        }
+        .map_err(|e| match e.downcast_ref::<AssetNonSpendable>() {
+            Some(e) => tonic::Status::failed_precondition(e.to_string()),
+            None => tonic::Status::unavailable(format!("error fetching notes: {}", e)),
+        })?;
 
         let stream = try_stream! {
             for note in notes {
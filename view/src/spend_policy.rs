@@ -0,0 +1,104 @@
+/// A policy determining which notes count as spendable right now.
+///
+/// The default policy counts every unspent, unquarantined note of an asset that isn't hidden,
+/// unless it's reserved for a send in progress, but not change which is yet to be confirmed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpendPolicy {
+    /// Notes with an amount below this threshold are considered dust, and are not counted.
+    pub dust_threshold: u64,
+    /// The number of blocks (including the one it was created in) a note must have been on chain
+    /// for before it is counted.
+    pub min_confirmations: u64,
+    /// Whether to count quarantined notes, which are locked until the end of an unbonding period.
+    pub include_quarantined: bool,
+    /// Whether to count notes of assets the user has hidden from balance views.
+    pub include_hidden: bool,
+    /// Whether to count notes reserved for a send in progress, which are locked until the send
+    /// completes or is cancelled.
+    pub include_reserved: bool,
+    /// Whether to count the change of submitted transactions which haven't been confirmed yet.
+    /// Such change has no confirmations, so it's only counted if `min_confirmations` is zero.
+    pub include_pending_change: bool,
+}
+
+impl SpendPolicy {
+    /// Whether a note of the given `amount`, created at `height_created`, is spendable under this
+    /// policy when the view service has synced to `sync_height`.
+    pub fn allows(&self, amount: u64, height_created: u64, sync_height: u64) -> bool {
+        let confirmations = sync_height.saturating_sub(height_created) + 1;
+        amount >= self.dust_threshold && confirmations >= self.min_confirmations
+    }
+
+    /// Whether pending change of the given `amount`, not yet on chain, is spendable under this
+    /// policy.
+    pub fn allows_pending(&self, amount: u64) -> bool {
+        self.include_pending_change && amount >= self.dust_threshold && self.min_confirmations == 0
+    }
+
+    /// The number of blocks after `sync_height` until a note created at `height_created` has the
+    /// confirmations required by this policy, or zero if it already has them.
+    pub fn blocks_until_confirmed(&self, height_created: u64, sync_height: u64) -> u64 {
+        let confirmations = sync_height.saturating_sub(height_created) + 1;
+        self.min_confirmations.saturating_sub(confirmations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policy_allows_everything() {
+        let policy = SpendPolicy::default();
+        assert!(policy.allows(1, 10, 10));
+    }
+
+    #[test]
+    fn dust_threshold_excludes_small_notes() {
+        let policy = SpendPolicy {
+            dust_threshold: 100,
+            ..Default::default()
+        };
+        assert!(!policy.allows(99, 0, 10));
+        assert!(policy.allows(100, 0, 10));
+    }
+
+    #[test]
+    fn pending_change_is_unconfirmed() {
+        let policy = SpendPolicy {
+            include_pending_change: true,
+            ..Default::default()
+        };
+        assert!(policy.allows_pending(1));
+        assert!(!SpendPolicy::default().allows_pending(1));
+        assert!(!SpendPolicy {
+            min_confirmations: 1,
+            ..policy
+        }
+        .allows_pending(1));
+    }
+
+    #[test]
+    fn min_confirmations_excludes_recent_notes() {
+        let policy = SpendPolicy {
+            min_confirmations: 6,
+            ..Default::default()
+        };
+        assert!(!policy.allows(100, 10, 14));
+        assert!(policy.allows(100, 10, 15));
+    }
+
+    #[test]
+    fn recent_notes_confirm_after_remaining_blocks() {
+        let policy = SpendPolicy {
+            min_confirmations: 6,
+            ..Default::default()
+        };
+        // Received 2 blocks ago, so it has 3 of the 6 confirmations.
+        assert_eq!(policy.blocks_until_confirmed(12, 14), 3);
+        assert!(!policy.allows(100, 12, 14 + 2));
+        assert!(policy.allows(100, 12, 14 + 3));
+        assert_eq!(policy.blocks_until_confirmed(12, 17), 0);
+        assert_eq!(policy.blocks_until_confirmed(12, 30), 0);
+    }
+}
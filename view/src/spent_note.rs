@@ -0,0 +1,54 @@
+use penumbra_crypto::{note, Nullifier, Value};
+
+use crate::NoteRecord;
+
+/// What remains of one of our spent notes after it has been tombstoned: enough to account for its
+/// value, but none of its plaintext, so that the recipient address, diversifier and blinding
+/// factor aren't kept at rest once the note is no longer needed for spending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpentNoteTombstone {
+    pub note_commitment: note::Commitment,
+    pub nullifier: Nullifier,
+    pub value: Value,
+    pub height_created: u64,
+    pub height_spent: u64,
+}
+
+impl SpentNoteTombstone {
+    /// The tombstone for the given note, or `None` if it hasn't been spent.
+    pub fn for_spent(record: &NoteRecord) -> Option<Self> {
+        Some(Self {
+            note_commitment: record.note_commitment,
+            nullifier: record.nullifier,
+            value: record.note.value(),
+            height_created: record.height_created,
+            height_spent: record.height_spent?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        STAKING_TOKEN_ASSET_ID,
+    };
+    use rand_core::OsRng;
+
+    use super::SpentNoteTombstone;
+    use crate::test_utils::note_record;
+
+    #[test]
+    fn tombstone_keeps_value_of_spent_notes_only() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let mut record = note_record(sk.full_viewing_key(), 0, 42, *STAKING_TOKEN_ASSET_ID, 1, 0);
+
+        assert_eq!(SpentNoteTombstone::for_spent(&record), None);
+
+        record.height_spent = Some(5);
+        let tombstone = SpentNoteTombstone::for_spent(&record).unwrap();
+        assert_eq!(tombstone.value, record.note.value());
+        assert_eq!(tombstone.nullifier, record.nullifier);
+        assert_eq!(tombstone.height_spent, 5);
+    }
+}
@@ -5,25 +5,120 @@ use parking_lot::Mutex;
 use penumbra_chain::params::ChainParams;
 use penumbra_crypto::{
     asset::{self, Id},
-    Asset, FieldExt, FullViewingKey, Nullifier,
+    keys::AddressIndex,
+    note, value, Asset, FieldExt, Fr, FullViewingKey, Nullifier, Value, STAKING_TOKEN_ASSET_ID,
 };
 use penumbra_proto::{
     client::oblivious::{oblivious_query_client::ObliviousQueryClient, ChainParamsRequest},
     Protobuf,
 };
 use penumbra_tct as tct;
-use penumbra_transaction::Transaction;
+use penumbra_transaction::{plan::TransactionPlan, Transaction};
+use rand_core::{CryptoRng, RngCore};
 use sha2::Digest;
-use sqlx::{migrate::MigrateDatabase, query, Pool, Sqlite};
-use std::{num::NonZeroU64, sync::Arc};
+use sqlx::{migrate::MigrateDatabase, query, Pool, Row, Sqlite};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    num::NonZeroU64,
+    sync::Arc,
+    time::SystemTime,
+};
 use tct::Commitment;
 use tokio::sync::broadcast;
 
-use crate::{sync::FilteredBlock, NoteRecord, QuarantinedNoteRecord};
+use crate::{
+    sync::{self, BlockScanResult, FilteredBlock, ScanStats},
+    transaction_record::{self, TransactionRecord},
+    Clock, NoteRecord, PaymentRequest, QuarantinedNoteRecord, SendSession, SpendPolicy,
+    SpentNoteTombstone, SystemClock, TransactionState,
+};
 
 mod nct;
 use nct::TreeStore;
 
+/// The default number of recent NCT anchors retained in memory by [`Storage::recent_anchors`];
+/// see [`Storage::with_max_recent_anchors`].
+pub const MAX_MERKLE_CHECKPOINTS_CLIENT: usize = 10;
+
+/// The number of blocks past the sync height at which they were made that the reservations of a
+/// [`SendSession`] last, if their notes aren't spent before then.
+pub const RESERVATION_EXPIRY_BLOCKS: u64 = 100;
+
+/// The number of recently scanned blocks whose statistics are retained by [`Storage::scan_history`].
+pub const MAX_SCAN_HISTORY: usize = 100;
+
+/// The default number of empty blocks which may be left uncommitted before they're committed by
+/// [`Storage::checkpoint_if_due`].
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// The maximum total size in bytes of the keys and JSON-encoded values stored with
+/// [`Storage::set_metadata`].
+pub const MAX_METADATA_BYTES: usize = 64 * 1024;
+
+/// The error returned by [`Storage::verify_anchor_against`] when our NCT root at some height
+/// doesn't match the expected one, indicating that our state is corrupt and should be resynced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnchorMismatch {
+    pub height: u64,
+    pub expected: tct::Root,
+    /// Our root at `height`, or `None` if it's no longer among the recent anchors.
+    pub actual: Option<tct::Root>,
+}
+
+impl std::fmt::Display for AnchorMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.actual {
+            Some(actual) => write!(
+                f,
+                "note commitment tree root at height {} is {}, but expected {}; resync the wallet",
+                self.height, actual, self.expected
+            ),
+            None => write!(
+                f,
+                "no recent note commitment tree root at height {} to check against {}",
+                self.height, self.expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AnchorMismatch {}
+
+/// The error returned when recording a block which doesn't directly follow the last block we
+/// scanned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnexpectedBlockHeight {
+    pub expected: u64,
+    pub got: u64,
+}
+
+impl std::fmt::Display for UnexpectedBlockHeight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "wrong block height {}, expected block at height {}",
+            self.got, self.expected
+        )
+    }
+}
+
+impl std::error::Error for UnexpectedBlockHeight {}
+
+/// The error returned when selecting notes for spending of an asset which was marked
+/// non-spendable with [`Storage::set_asset_spendable`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AssetNonSpendable {
+    pub asset_id: asset::Id,
+}
+
+impl std::fmt::Display for AssetNonSpendable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "asset {} is not spendable", self.asset_id)
+    }
+}
+
+impl std::error::Error for AssetNonSpendable {}
+
 #[derive(Clone)]
 pub struct Storage {
     pool: Pool<Sqlite>,
@@ -37,21 +132,61 @@ pub struct Storage {
     /// Using a `NonZeroU64` ensures that `Option<NonZeroU64>` fits in 8 bytes.
     uncommitted_height: Arc<Mutex<Option<NonZeroU64>>>,
 
+    /// The number of empty blocks which may be left uncommitted before they're committed anyway,
+    /// so that a long sync through empty blocks doesn't lose all its progress if interrupted.
+    checkpoint_interval: u64,
+
+    /// The NCT roots observed after each recently scanned block, oldest first, bounded to
+    /// `max_recent_anchors` entries.
+    recent_anchors: Arc<Mutex<VecDeque<(u64, tct::Root)>>>,
+
+    /// The number of recent NCT anchors to retain, [`MAX_MERKLE_CHECKPOINTS_CLIENT`] by default.
+    max_recent_anchors: usize,
+
+    /// Statistics about each recently scanned block, oldest first, bounded to
+    /// [`MAX_SCAN_HISTORY`] entries.
+    scan_history: Arc<Mutex<VecDeque<(u64, ScanStats)>>>,
+
+    /// The source of wall-clock time, and the time at which we last recorded a block.
+    clock: Arc<dyn Clock>,
+    last_sync_time: Arc<Mutex<Option<SystemTime>>>,
+
     scanned_notes_tx: tokio::sync::broadcast::Sender<NoteRecord>,
     scanned_nullifiers_tx: tokio::sync::broadcast::Sender<Nullifier>,
 }
 
 impl Storage {
     /// If the database at `storage_path` exists, [`Self::load`] it, otherwise, [`Self::initialize`] it.
+    ///
+    /// If `verify` is set, an existing database is loaded with [`Self::load_verified`] instead.
     pub async fn load_or_initialize(
         storage_path: impl AsRef<Utf8Path>,
         fvk: &FullViewingKey,
         node: String,
         pd_port: u16,
+        verify: bool,
     ) -> anyhow::Result<Self> {
         let storage_path = storage_path.as_ref();
         if storage_path.exists() {
-            Self::load(storage_path.as_str()).await
+            let storage = if verify {
+                Self::load_verified(storage_path.as_str()).await?
+            } else {
+                Self::load(storage_path.as_str()).await?
+            };
+
+            // Refuse to use a database scanned with a different full viewing key, since
+            // continuing to scan with this one would silently derive the wrong nullifiers.
+            let stored_fvk_hash = storage.full_viewing_key().await?.hash();
+            if stored_fvk_hash != fvk.hash() {
+                return Err(anyhow!(
+                    "database at {} belongs to a different wallet (fingerprint {}, expected {})",
+                    storage_path,
+                    hex::encode(stored_fvk_hash.fingerprint()),
+                    hex::encode(fvk.hash().fingerprint()),
+                ));
+            }
+
+            Ok(storage)
         } else {
             let mut client =
                 ObliviousQueryClient::connect(format!("http://{}:{}", node, pd_port)).await?;
@@ -96,11 +231,48 @@ impl Storage {
         Ok(Self {
             pool: Self::connect(path.as_ref().as_str()).await?,
             uncommitted_height: Arc::new(Mutex::new(None)),
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            recent_anchors: Arc::new(Mutex::new(VecDeque::new())),
+            max_recent_anchors: MAX_MERKLE_CHECKPOINTS_CLIENT,
+            scan_history: Arc::new(Mutex::new(VecDeque::new())),
+            clock: Arc::new(SystemClock),
+            last_sync_time: Arc::new(Mutex::new(None)),
             scanned_notes_tx: broadcast::channel(10).0,
             scanned_nullifiers_tx: broadcast::channel(10).0,
         })
     }
 
+    /// Load the storage at `path`, then check the integrity of its stored nullifiers with
+    /// [`Storage::verify_nullifiers`].
+    ///
+    /// This is slower than [`Storage::load`], since it re-derives the nullifier of every note.
+    pub async fn load_verified(path: impl AsRef<Utf8Path>) -> anyhow::Result<Self> {
+        let storage = Self::load(path).await?;
+        storage.verify_nullifiers().await?;
+        Ok(storage)
+    }
+
+    /// Write a copy of the committed storage to a new database at `path`, e.g. to hand to a watch
+    /// wallet or monitoring service, which can [`Storage::load`] it to keep scanning and report
+    /// balances.
+    ///
+    /// The storage only ever holds the full viewing key, never spending keys (which are kept in the
+    /// wallet's `KeyStore`), so the copy can't be used to spend. Empty blocks not yet committed by
+    /// [`Storage::checkpoint_if_due`] aren't included, and are rescanned by the copy.
+    pub async fn export_copy(&self, path: impl AsRef<Utf8Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(anyhow!("refusing to overwrite existing file {}", path));
+        }
+
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn initialize(
         storage_path: impl AsRef<Utf8Path>,
         fvk: FullViewingKey,
@@ -152,11 +324,45 @@ impl Storage {
         Ok(Storage {
             pool,
             uncommitted_height: Arc::new(Mutex::new(None)),
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            recent_anchors: Arc::new(Mutex::new(VecDeque::new())),
+            max_recent_anchors: MAX_MERKLE_CHECKPOINTS_CLIENT,
+            scan_history: Arc::new(Mutex::new(VecDeque::new())),
+            clock: Arc::new(SystemClock),
+            last_sync_time: Arc::new(Mutex::new(None)),
             scanned_notes_tx: broadcast::channel(10).0,
             scanned_nullifiers_tx: broadcast::channel(10).0,
         })
     }
 
+    /// Use the given [`Clock`] to read wall-clock time, rather than the system time.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Commit uncommitted empty blocks once `interval` of them have accumulated, rather than
+    /// [`DEFAULT_CHECKPOINT_INTERVAL`].
+    pub fn with_checkpoint_interval(mut self, interval: u64) -> Self {
+        self.checkpoint_interval = interval.max(1);
+        self
+    }
+
+    /// Retain the `max` most recent NCT anchors, rather than [`MAX_MERKLE_CHECKPOINTS_CLIENT`].
+    ///
+    /// This bounds how far back [`Storage::verify_anchor_against`] can check, and how old an
+    /// anchor a client can pick from [`Storage::recent_anchors`]. Light clients may retain fewer,
+    /// and archival clients more, but at least one anchor must be retained, so `max` must not be
+    /// zero. Anchors already recorded beyond the new bound are dropped, oldest first.
+    pub fn with_max_recent_anchors(mut self, max: usize) -> anyhow::Result<Self> {
+        if max == 0 {
+            return Err(anyhow!("at least one recent anchor must be retained"));
+        }
+        self.max_recent_anchors = max;
+        push_bounded(&mut self.recent_anchors.lock(), None, max);
+        Ok(self)
+    }
+
     /// Query for a note by its note commitment, optionally waiting until the note is detected.
     pub fn note_by_commitment(
         &self,
@@ -202,6 +408,28 @@ impl Storage {
         }
     }
 
+    /// Check whether we track the given note commitment at all, whether its note is unspent,
+    /// spent (including tombstoned), or quarantined.
+    pub async fn tracks_commitment(
+        &self,
+        note_commitment: note::Commitment,
+    ) -> anyhow::Result<bool> {
+        let note_commitment = note_commitment.0.to_bytes().to_vec();
+
+        let tracked = sqlx::query(
+            "SELECT EXISTS (SELECT 1 FROM notes WHERE note_commitment = ?1)
+            OR EXISTS (SELECT 1 FROM quarantined_notes WHERE note_commitment = ?1)
+            OR EXISTS (SELECT 1 FROM spent_note_tombstones WHERE note_commitment = ?1)
+            AS tracked",
+        )
+        .bind(note_commitment)
+        .fetch_one(&self.pool)
+        .await?
+        .get::<i64, _>("tracked");
+
+        Ok(tracked != 0)
+    }
+
     /// Query for a nullifier's status, optionally waiting until the nullifier is detected.
     pub fn nullifier_status(
         &self,
@@ -252,6 +480,20 @@ impl Storage {
         }
     }
 
+    /// The commitment of our note which the given nullifier spends, if any.
+    pub async fn note_commitment_for_nullifier(
+        &self,
+        nullifier: &Nullifier,
+    ) -> anyhow::Result<Option<note::Commitment>> {
+        sqlx::query("SELECT note_commitment FROM notes WHERE nullifier = ?")
+            .bind(nullifier.0.to_bytes().to_vec())
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| note::Commitment::try_from(row.get::<&[u8], _>("note_commitment")))
+            .transpose()
+            .map_err(Into::into)
+    }
+
     /// The last block height we've scanned to, if any.
     pub async fn last_sync_height(&self) -> anyhow::Result<Option<u64>> {
         // Check if we have uncommitted blocks beyond the database height.
@@ -274,6 +516,110 @@ impl Storage {
         Ok(u64::try_from(result.height).ok())
     }
 
+    /// The heights among `relevant` which we have yet to scan (see
+    /// [`sync::missing_relevant_heights`], including the requirement to still advance the note
+    /// commitment tree past the blocks in between).
+    pub async fn missing_relevant_heights(&self, relevant: &[u64]) -> anyhow::Result<Vec<u64>> {
+        Ok(sync::missing_relevant_heights(
+            self.last_sync_height().await?,
+            relevant.iter().copied(),
+        ))
+    }
+
+    /// If enough empty blocks have been recorded without being committed (see
+    /// [`Storage::with_checkpoint_interval`]), commit them along with `nct`, which must reflect
+    /// the state after the last of them, returning whether a checkpoint was committed.
+    ///
+    /// The committed state is always at a block boundary, so if syncing is interrupted it resumes
+    /// from the checkpoint rather than the last nonempty block.
+    pub async fn checkpoint_if_due(&self, nct: &mut tct::Tree) -> anyhow::Result<bool> {
+        let uncommitted_height = match *self.uncommitted_height.lock() {
+            Some(height) => height.get(),
+            None => return Ok(false),
+        };
+
+        let committed_height = sqlx::query("SELECT height FROM sync_height")
+            .fetch_one(&self.pool)
+            .await?
+            .get::<i64, _>("height");
+        if !checkpoint_due(
+            committed_height,
+            uncommitted_height,
+            self.checkpoint_interval,
+        ) {
+            return Ok(false);
+        }
+
+        let mut dbtx = self.pool.begin().await?;
+        nct.serialize(&mut TreeStore(&mut dbtx)).await?;
+        sqlx::query("UPDATE sync_height SET height = ?")
+            .bind(uncommitted_height as i64)
+            .execute(&mut dbtx)
+            .await?;
+        dbtx.commit().await?;
+
+        // Only forget the uncommitted height if no further block was recorded meanwhile.
+        let mut uncommitted = self.uncommitted_height.lock();
+        if uncommitted.map(NonZeroU64::get) == Some(uncommitted_height) {
+            uncommitted.take();
+        }
+
+        Ok(true)
+    }
+
+    /// The wall-clock time at which a block was last recorded, if any block has been recorded
+    /// since this [`Storage`] was loaded.
+    pub fn last_sync_time(&self) -> Option<SystemTime> {
+        *self.last_sync_time.lock()
+    }
+
+    /// The NCT roots recorded after each recently scanned block, oldest first.
+    ///
+    /// At most [`MAX_MERKLE_CHECKPOINTS_CLIENT`] anchors are retained by default (see
+    /// [`Storage::with_max_recent_anchors`]), so a client can build a transaction against a
+    /// slightly older, but still valid, anchor if needed.
+    pub fn recent_anchors(&self) -> Vec<(u64, tct::Root)> {
+        self.recent_anchors.lock().iter().copied().collect()
+    }
+
+    /// Check that the NCT root we reached after scanning the block at `height` is
+    /// `expected_root`, e.g. as reported by the server, to detect a corrupt or forked state.
+    ///
+    /// Only the [`Storage::recent_anchors`] can be checked; an older `height` is reported as a
+    /// mismatch with no actual root.
+    pub fn verify_anchor_against(
+        &self,
+        height: u64,
+        expected_root: tct::Root,
+    ) -> Result<(), AnchorMismatch> {
+        verify_anchor(&self.recent_anchors.lock(), height, expected_root)
+    }
+
+    /// Record the NCT root reached after scanning the block at `height`.
+    pub fn record_anchor(&self, height: u64, anchor: tct::Root) {
+        push_bounded(
+            &mut self.recent_anchors.lock(),
+            Some((height, anchor)),
+            self.max_recent_anchors,
+        );
+    }
+
+    /// Statistics about each recently scanned block, oldest first.
+    ///
+    /// Empty blocks, which are not scanned, are not included.
+    pub fn scan_history(&self) -> Vec<(u64, ScanStats)> {
+        self.scan_history.lock().iter().copied().collect()
+    }
+
+    /// Record statistics about scanning the block at `height`.
+    pub fn record_scan_stats(&self, height: u64, stats: ScanStats) {
+        let mut scan_history = self.scan_history.lock();
+        if scan_history.len() >= MAX_SCAN_HISTORY {
+            scan_history.pop_front();
+        }
+        scan_history.push_back((height, stats));
+    }
+
     pub async fn chain_params(&self) -> anyhow::Result<ChainParams> {
         let result = query!(
             r#"
@@ -302,6 +648,57 @@ impl Storage {
         FullViewingKey::decode(result.bytes.as_slice())
     }
 
+    /// A short, non-sensitive fingerprint of the full viewing key this storage was initialized
+    /// with, which can be displayed and compared to confirm the right wallet is loaded.
+    pub async fn wallet_fingerprint(&self) -> anyhow::Result<[u8; 8]> {
+        Ok(self.full_viewing_key().await?.hash().fingerprint())
+    }
+
+    /// Re-derive the nullifier of every stored note from its position and commitment, and check
+    /// that it matches the stored nullifier.
+    ///
+    /// A mismatch means the database is corrupt or has been tampered with: since spends are
+    /// detected by nullifier, a wrong nullifier could make a spent note appear unspent.
+    pub async fn verify_nullifiers(&self) -> anyhow::Result<()> {
+        self.check_nullifiers(&self.full_viewing_key().await?).await
+    }
+
+    /// Replace the stored full viewing key with `fvk`, e.g. when upgrading its encoding, keeping
+    /// all scanned notes.
+    ///
+    /// This fails unless `fvk` derives the same nullifier as the one stored for every note, since
+    /// otherwise spends of existing notes would no longer be detected.
+    pub async fn replace_full_viewing_key(&self, fvk: &FullViewingKey) -> anyhow::Result<()> {
+        self.check_nullifiers(fvk)
+            .await
+            .context("new full viewing key is not equivalent to the stored one")?;
+
+        let fvk_bytes = fvk.encode_to_vec();
+        sqlx::query("UPDATE full_viewing_key SET bytes = ?")
+            .bind(&fvk_bytes)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Check that `fvk` derives the stored nullifier of every stored note.
+    async fn check_nullifiers(&self, fvk: &FullViewingKey) -> anyhow::Result<()> {
+        for record in self.notes(true, None, None, 0, true).await? {
+            let nullifier = fvk.derive_nullifier(record.position, &record.note_commitment);
+            if nullifier != record.nullifier {
+                return Err(anyhow!(
+                    "stored nullifier {:?} for note {:?} does not match derived nullifier {:?}",
+                    record.nullifier,
+                    record.note_commitment,
+                    nullifier
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn note_commitment_tree(&self) -> anyhow::Result<tct::Tree> {
         let mut tx = self.pool.begin().await?;
         let tree = tct::Tree::deserialize(&mut TreeStore(&mut tx)).await?;
@@ -309,111 +706,1185 @@ impl Storage {
         Ok(tree)
     }
 
-    pub async fn assets(&self) -> anyhow::Result<Vec<Asset>> {
-        let result = sqlx::query!(
-            "SELECT *
-            FROM assets"
+    pub async fn assets(&self) -> anyhow::Result<Vec<Asset>> {
+        let result = sqlx::query!(
+            "SELECT *
+            FROM assets"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut output: Vec<Asset> = Vec::new();
+
+        for record in result {
+            let asset = Asset {
+                id: Id::try_from(record.asset_id.as_slice())?,
+                denom: asset::REGISTRY
+                    .parse_denom(&record.denom)
+                    .ok_or_else(|| anyhow::anyhow!("invalid denomination {}", record.denom))?,
+            };
+            output.push(asset);
+        }
+
+        Ok(output)
+    }
+
+    /// The denominations of every asset the wallet has ever received, whether or not the notes
+    /// holding it have since been spent, tombstoned or pruned, de-duplicated and sorted.
+    pub async fn known_denoms(&self) -> anyhow::Result<Vec<String>> {
+        Ok(sqlx::query(
+            "SELECT DISTINCT denom
+            FROM assets
+            WHERE asset_id IN (SELECT asset_id FROM notes)
+            OR asset_id IN (SELECT asset_id FROM quarantined_notes)
+            OR asset_id IN (SELECT asset_id FROM spent_note_tombstones)
+            OR asset_id IN (SELECT asset_id FROM pruned_note_assets)
+            ORDER BY denom",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get::<String, _>("denom"))
+        .collect())
+    }
+
+    /// The IDs of assets of our notes whose denominations we haven't stored, e.g. because a note
+    /// was scanned before its asset was synced, so that a client can fetch their metadata.
+    pub async fn unknown_assets(&self) -> anyhow::Result<Vec<asset::Id>> {
+        sqlx::query(
+            "SELECT DISTINCT asset_id
+            FROM (
+                SELECT asset_id FROM notes
+                UNION
+                SELECT asset_id FROM quarantined_notes
+            )
+            WHERE asset_id NOT IN (SELECT asset_id FROM assets)",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| Id::try_from(row.get::<&[u8], _>("asset_id")))
+        .collect()
+    }
+
+    /// Groups of known assets whose denominations differ only in case, so that a client can warn
+    /// that their balances are displayed separately.
+    ///
+    /// These can't be merged: an asset's ID is derived from its exact denomination, so assets whose
+    /// denominations differ in any way are distinct assets on chain, and notes of one can't be
+    /// spent as the other.
+    pub async fn denom_collisions(&self) -> anyhow::Result<Vec<Vec<Asset>>> {
+        Ok(denom_collisions(self.assets().await?))
+    }
+
+    pub async fn notes(
+        &self,
+        include_spent: bool,
+        asset_id: Option<asset::Id>,
+        address_index: Option<penumbra_crypto::keys::AddressIndex>,
+        amount_to_spend: u64,
+        include_hidden: bool,
+    ) -> anyhow::Result<Vec<NoteRecord>> {
+        let result = sqlx::query_as::<_, NoteRecord>(&notes_query(
+            include_spent,
+            asset_id,
+            address_index,
+            include_hidden,
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        // If set, stop returning notes once the total exceeds this amount.
+        //
+        // Ignored if `asset_id` is unset or if `include_spent` is set.
+        // uint64 amount_to_spend = 5;
+        //TODO: figure out a clever way to only return notes up to the sum using SQL
+        let amount_cutoff = !(include_spent || asset_id.is_none());
+        notes_covering(result, amount_cutoff, amount_to_spend)
+    }
+
+    /// Select unspent notes of `asset_id` for spending, as [`Storage::notes`] does, failing with
+    /// [`AssetNonSpendable`] for an asset marked non-spendable with
+    /// [`Storage::set_asset_spendable`].
+    ///
+    /// Notes of hidden assets are included, since hiding an asset only affects balance views.
+    pub async fn spendable_notes(
+        &self,
+        asset_id: asset::Id,
+        address_index: Option<AddressIndex>,
+        amount_to_spend: u64,
+    ) -> anyhow::Result<Vec<NoteRecord>> {
+        if !self.is_asset_spendable(asset_id).await? {
+            return Err(AssetNonSpendable { asset_id }.into());
+        }
+
+        self.notes(false, Some(asset_id), address_index, amount_to_spend, true)
+            .await
+    }
+
+    /// Begin a send of `amount` of `asset_id`, reserving the notes selected for it until the
+    /// returned session is finalized or cancelled.
+    ///
+    /// Like [`Storage::spendable_notes`], this fails with [`AssetNonSpendable`] for an asset
+    /// marked non-spendable.
+    pub async fn begin_send(
+        &self,
+        asset_id: asset::Id,
+        amount: u64,
+    ) -> anyhow::Result<SendSession> {
+        SendSession::begin(self.clone(), asset_id, amount).await
+    }
+
+    /// Select unspent notes of `asset_id` covering at least `amount`, as
+    /// [`Storage::spendable_notes`] does, and reserve them, excluding them from the unspent notes
+    /// returned by [`Storage::notes`]. The notes are selected and reserved in a single database
+    /// transaction, which fails if any of them was reserved concurrently.
+    ///
+    /// The reservation ends when the notes' spend is scanned, or lapses once
+    /// [`RESERVATION_EXPIRY_BLOCKS`] blocks past the current sync height are scanned without it,
+    /// e.g. because the send was never broadcast.
+    pub(crate) async fn reserve_spendable_notes(
+        &self,
+        asset_id: asset::Id,
+        amount: u64,
+    ) -> anyhow::Result<Vec<NoteRecord>> {
+        let expiry_height = self.last_sync_height().await?.unwrap_or(0) + RESERVATION_EXPIRY_BLOCKS;
+        let mut dbtx = self.pool.begin().await?;
+
+        let non_spendable =
+            sqlx::query("SELECT EXISTS(SELECT 1 FROM non_spendable_assets WHERE asset_id = ?)")
+                .bind(asset_id.to_bytes().to_vec())
+                .fetch_one(&mut dbtx)
+                .await?
+                .get::<i64, _>(0)
+                != 0;
+        if non_spendable {
+            return Err(AssetNonSpendable { asset_id }.into());
+        }
+
+        let query = notes_query(false, Some(asset_id), None, true);
+        let notes = notes_covering(
+            sqlx::query_as::<_, NoteRecord>(&query)
+                .fetch_all(&mut dbtx)
+                .await?,
+            true,
+            amount,
+        )?;
+
+        for record in &notes {
+            let reserved = sqlx::query(
+                "INSERT OR IGNORE INTO reserved_notes (note_commitment, expiry_height) VALUES (?, ?)",
+            )
+            .bind(record.note_commitment.0.to_bytes().to_vec())
+            .bind(expiry_height as i64)
+            .execute(&mut dbtx)
+            .await?
+            .rows_affected();
+            if reserved == 0 {
+                // Dropping the transaction releases the notes reserved so far.
+                return Err(anyhow!(
+                    "note {} is already reserved by another send",
+                    record.note_commitment
+                ));
+            }
+        }
+        dbtx.commit().await?;
+
+        Ok(notes)
+    }
+
+    /// The commitments of the notes currently reserved by send sessions.
+    pub async fn reserved_notes(&self) -> anyhow::Result<Vec<note::Commitment>> {
+        sqlx::query("SELECT note_commitment FROM reserved_notes")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| note::Commitment::try_from(row.get::<&[u8], _>("note_commitment")))
+            .collect()
+    }
+
+    /// Release notes reserved by a send session.
+    pub async fn release_notes(
+        &self,
+        note_commitments: impl IntoIterator<Item = note::Commitment>,
+    ) -> anyhow::Result<()> {
+        let mut dbtx = self.pool.begin().await?;
+        for commitment in note_commitments {
+            sqlx::query("DELETE FROM reserved_notes WHERE note_commitment = ?")
+                .bind(commitment.0.to_bytes().to_vec())
+                .execute(&mut dbtx)
+                .await?;
+        }
+        dbtx.commit().await?;
+
+        Ok(())
+    }
+
+    /// The commitments of received notes, spent or unspent, which satisfy the given payment
+    /// request.
+    pub async fn match_payment_request(
+        &self,
+        request: &PaymentRequest,
+    ) -> anyhow::Result<Vec<note::Commitment>> {
+        Ok(self
+            .notes(
+                true,
+                Some(request.asset_id),
+                Some(request.address_index),
+                0,
+                true,
+            )
+            .await?
+            .into_iter()
+            .filter(|record| request.is_satisfied_by(record))
+            .map(|record| record.note_commitment)
+            .collect())
+    }
+
+    /// The total amount of each asset which is spendable right now under the given policy.
+    pub async fn available_balance(
+        &self,
+        policy: &SpendPolicy,
+    ) -> anyhow::Result<BTreeMap<asset::Id, u64>> {
+        let sync_height = self.last_sync_height().await?.unwrap_or(0);
+
+        let mut balance = BTreeMap::<asset::Id, u64>::new();
+        for record in self
+            .notes(false, None, None, 0, policy.include_hidden)
+            .await?
+        {
+            if policy.allows(record.note.amount(), record.height_created, sync_height) {
+                *balance.entry(record.note.asset_id()).or_default() += record.note.amount();
+            }
+        }
+
+        let hidden = match policy.include_hidden {
+            false => self.hidden_assets().await?,
+            true => Vec::new(),
+        };
+
+        if policy.include_reserved {
+            let reserved = sqlx::query_as::<_, NoteRecord>(
+                "SELECT * FROM notes
+                WHERE height_spent IS NULL
+                AND note_commitment IN (SELECT note_commitment FROM reserved_notes)
+                AND note_commitment NOT IN (SELECT note_commitment FROM pending_spends)",
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            for record in reserved {
+                if hidden.contains(&record.note.asset_id()) {
+                    continue;
+                }
+                if policy.allows(record.note.amount(), record.height_created, sync_height) {
+                    *balance.entry(record.note.asset_id()).or_default() += record.note.amount();
+                }
+            }
+        }
+
+        if policy.include_pending_change {
+            for (_, value) in self.pending_change().await? {
+                if !hidden.contains(&value.asset_id) && policy.allows_pending(value.amount) {
+                    *balance.entry(value.asset_id).or_default() += value.amount;
+                }
+            }
+        }
+
+        if policy.include_quarantined {
+            for record in self.quarantined_notes().await? {
+                if hidden.contains(&record.note.asset_id()) {
+                    continue;
+                }
+                if policy.allows(record.note.amount(), record.height_created, sync_height) {
+                    *balance.entry(record.note.asset_id()).or_default() += record.note.amount();
+                }
+            }
+        }
+
+        Ok(balance)
+    }
+
+    /// The total amount of each asset held in our unspent notes, keyed by denomination.
+    ///
+    /// Notes reserved for an in-progress send are not included, nor are notes of hidden assets
+    /// unless `include_hidden` is set. Notes of assets whose denomination is unknown are keyed by
+    /// their asset ID.
+    pub async fn balances(&self, include_hidden: bool) -> anyhow::Result<BTreeMap<String, u64>> {
+        let denoms = self
+            .assets()
+            .await?
+            .into_iter()
+            .map(|asset| (asset.id, asset.denom.to_string()))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut balances = BTreeMap::<String, u64>::new();
+        for record in self.notes(false, None, None, 0, include_hidden).await? {
+            let asset_id = record.note.asset_id();
+            let denom = denoms
+                .get(&asset_id)
+                .cloned()
+                .unwrap_or_else(|| asset_id.to_string());
+            *balances.entry(denom).or_default() += record.note.amount();
+        }
+
+        Ok(balances)
+    }
+
+    /// The total amount of each asset held in our unspent notes at each of our addresses, keyed
+    /// by address index and then by denomination as in [`Storage::balances`], so a client can show
+    /// which of its addresses hold funds.
+    pub async fn balances_by_address(
+        &self,
+        include_hidden: bool,
+    ) -> anyhow::Result<BTreeMap<AddressIndex, BTreeMap<String, u64>>> {
+        let denoms = self
+            .assets()
+            .await?
+            .into_iter()
+            .map(|asset| (asset.id, asset.denom.to_string()))
+            .collect::<BTreeMap<_, _>>();
+
+        Ok(balances_by_address(
+            &denoms,
+            self.notes(false, None, None, 0, include_hidden)
+                .await?
+                .into_iter()
+                .map(|record| {
+                    (
+                        record.address_index,
+                        record.note.asset_id(),
+                        record.note.amount(),
+                    )
+                }),
+        ))
+    }
+
+    /// The total amount of the asset with the given denomination held in our unspent notes; see
+    /// [`Storage::balances`].
+    pub async fn balance(&self, denom: &str, include_hidden: bool) -> anyhow::Result<u64> {
+        Ok(self
+            .balances(include_hidden)
+            .await?
+            .get(denom)
+            .copied()
+            .unwrap_or(0))
+    }
+
+    /// The total amount of each asset held in our unspent notes, including notes reserved for an
+    /// in-progress send or spent by a submitted transaction which hasn't been scanned yet.
+    ///
+    /// This is the balance the chain itself attributes to us as of the last synced block.
+    pub async fn unspent_totals(&self) -> anyhow::Result<BTreeMap<asset::Id, u64>> {
+        let mut totals = BTreeMap::<asset::Id, u64>::new();
+        for record in
+            sqlx::query_as::<_, NoteRecord>("SELECT * FROM notes WHERE height_spent IS NULL")
+                .fetch_all(&self.pool)
+                .await?
+        {
+            *totals.entry(record.note.asset_id()).or_default() += record.note.amount();
+        }
+
+        Ok(totals)
+    }
+
+    /// The earliest height at which one of our notes was created, if any.
+    ///
+    /// Since the view service scans from genesis and doesn't record a wallet birthday, no earlier
+    /// block can be relevant to this wallet, which bounds how far back a client needs to query.
+    pub async fn earliest_relevant_height(&self) -> anyhow::Result<Option<u64>> {
+        let row = sqlx::query(
+            "SELECT MIN(height_created) AS height FROM (
+                SELECT height_created FROM notes
+                UNION ALL
+                SELECT height_created FROM quarantined_notes
+            )",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row
+            .get::<Option<i64>, _>("height")
+            .map(|height| height as u64))
+    }
+
+    /// The narrowest range of block heights, inclusive, covering the lifecycle of the note with the
+    /// given commitment: from the height it was created at to the height it was spent at, or the
+    /// latest synced height if it is unspent.
+    ///
+    /// Rescanning just this range is enough to re-detect the note and its spend. Returns `None` if
+    /// the note is unknown.
+    pub async fn rescan_range_for_note(
+        &self,
+        note_commitment: &note::Commitment,
+    ) -> anyhow::Result<Option<(u64, u64)>> {
+        let row =
+            sqlx::query("SELECT height_created, height_spent FROM notes WHERE note_commitment = ?")
+                .bind(note_commitment.0.to_bytes().to_vec())
+                .fetch_optional(&self.pool)
+                .await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let height_created = row.get::<i64, _>("height_created") as u64;
+        let end_height = match row.get::<Option<i64>, _>("height_spent") {
+            Some(height_spent) => height_spent as u64,
+            None => self.last_sync_height().await?.unwrap_or(height_created),
+        };
+
+        Ok(Some((height_created, end_height)))
+    }
+
+    /// The age, in blocks, of the note with the given commitment relative to the latest synced
+    /// height, or `None` if the note or the sync height is unknown.
+    pub async fn note_age(
+        &self,
+        note_commitment: &note::Commitment,
+    ) -> anyhow::Result<Option<u64>> {
+        let row = sqlx::query("SELECT height_created FROM notes WHERE note_commitment = ?")
+            .bind(note_commitment.0.to_bytes().to_vec())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let height_created = match row {
+            Some(row) => row.get::<i64, _>("height_created") as u64,
+            None => return Ok(None),
+        };
+
+        Ok(self
+            .last_sync_height()
+            .await?
+            .and_then(|sync_height| note_age(height_created, sync_height)))
+    }
+
+    /// Our unspent notes which don't yet have the confirmations required by `policy`, with the
+    /// number of blocks remaining until they do, so that a client can show when they'll become
+    /// spendable.
+    pub async fn maturing_notes(
+        &self,
+        policy: &SpendPolicy,
+    ) -> anyhow::Result<Vec<(note::Commitment, u64)>> {
+        let sync_height = self.last_sync_height().await?.unwrap_or(0);
+
+        Ok(self
+            .notes(false, None, None, 0, true)
+            .await?
+            .into_iter()
+            .filter_map(|record| {
+                let remaining = policy.blocks_until_confirmed(record.height_created, sync_height);
+                (remaining > 0).then(|| (record.note_commitment, remaining))
+            })
+            .collect())
+    }
+
+    /// The change that would result from spending exactly the notes with the given commitments to
+    /// pay `amount` (plus `fee`, if the notes are of the staking token), so that a client can
+    /// preview the change of a manual note selection.
+    ///
+    /// This fails if any of the notes is unknown or already spent, if the notes are of more than
+    /// one asset, or if they don't cover the amount to pay.
+    pub async fn change_if_spent(
+        &self,
+        note_commitments: &[note::Commitment],
+        amount: u64,
+        fee: u64,
+    ) -> anyhow::Result<u64> {
+        let mut asset_id = None;
+        let mut selected = 0u64;
+        for commitment in note_commitments {
+            let record = self.note_by_commitment(*commitment, false).await?;
+            if record.height_spent.is_some() {
+                return Err(anyhow!("note {} has already been spent", commitment));
+            }
+            if *asset_id.get_or_insert(record.note.asset_id()) != record.note.asset_id() {
+                return Err(anyhow!("selected notes are of more than one asset"));
+            }
+            selected += record.note.amount();
+        }
+
+        let target = if asset_id == Some(*STAKING_TOKEN_ASSET_ID) {
+            amount + fee
+        } else {
+            amount
+        };
+
+        selected.checked_sub(target).ok_or_else(|| {
+            anyhow!(
+                "selected notes total {}, which does not cover the required {}",
+                selected,
+                target
+            )
+        })
+    }
+
+    /// A score from 0 to 100 measuring how fragmented the unspent notes of the given asset are,
+    /// to suggest consolidating them: 0 means a single note, and it approaches 100 as the notes
+    /// become more numerous and the smallest note a smaller share of the total.
+    pub async fn fragmentation_score(&self, asset_id: asset::Id) -> anyhow::Result<f64> {
+        let amounts = self
+            .notes(false, Some(asset_id), None, 0, true)
+            .await?
+            .into_iter()
+            .map(|record| record.note.amount())
+            .collect::<Vec<_>>();
+
+        Ok(fragmentation_score(&amounts))
+    }
+
+    /// A commitment to our total unspent balance of the given asset, and the blinding factor which
+    /// opens it, e.g. to prove holding a balance without revealing how it's split among notes.
+    ///
+    /// See [`value::commit_to_total`].
+    pub async fn balance_commitment<R: RngCore + CryptoRng>(
+        &self,
+        asset_id: asset::Id,
+        rng: R,
+    ) -> anyhow::Result<(value::Commitment, Fr)> {
+        let values = self
+            .notes(false, Some(asset_id), None, 0, true)
+            .await?
+            .into_iter()
+            .map(|record| record.note.value());
+
+        Ok(value::commit_to_total(values, rng))
+    }
+
+    /// The amounts of the smallest and largest unspent notes of each asset, e.g. to show the most
+    /// that can be paid with a single note, or to detect dust.
+    pub async fn note_extremes(&self) -> anyhow::Result<BTreeMap<asset::Id, (u64, u64)>> {
+        Ok(note_extremes(
+            self.notes(false, None, None, 0, true)
+                .await?
+                .iter()
+                .map(|record| (record.note.asset_id(), record.note.amount())),
+        ))
+    }
+
+    /// The unspent notes of `asset_id` which each on their own pay at least `minimum`, smallest
+    /// first, e.g. to suggest a single note satisfying a recipient's minimum payment.
+    pub async fn notes_meeting_minimum(
+        &self,
+        asset_id: asset::Id,
+        minimum: u64,
+    ) -> anyhow::Result<Vec<NoteRecord>> {
+        Ok(meeting_minimum(
+            self.notes(false, Some(asset_id), None, 0, true).await?,
+            minimum,
+            |record| record.note.amount(),
+        ))
+    }
+
+    /// The commitments of our notes, spent or unspent, whose displayed denomination would change
+    /// if our asset registry were refreshed from the `authoritative` denominations, e.g. because a
+    /// denomination was corrected after we recorded it, or we never learned it.
+    pub async fn stale_denom_notes(
+        &self,
+        authoritative: &BTreeMap<asset::Id, String>,
+    ) -> anyhow::Result<Vec<note::Commitment>> {
+        let local = self
+            .assets()
+            .await?
+            .into_iter()
+            .map(|asset| (asset.id, asset.denom.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        let stale = stale_denoms(&local, authoritative);
+
+        Ok(self
+            .notes(true, None, None, 0, true)
+            .await?
+            .into_iter()
+            .filter(|record| stale.contains(&record.note.asset_id()))
+            .map(|record| record.note_commitment)
+            .collect())
+    }
+
+    /// The address indices which have received more than one note, spent or unspent, with the
+    /// number of notes each received, so a client can warn about address reuse.
+    pub async fn reused_receive_addresses(&self) -> anyhow::Result<Vec<(AddressIndex, usize)>> {
+        sqlx::query(
+            "SELECT address_index, COUNT(*) AS count
+            FROM notes
+            GROUP BY address_index
+            HAVING COUNT(*) > 1",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok((
+                AddressIndex::try_from(row.get::<&[u8], _>("address_index"))?,
+                row.get::<i64, _>("count") as usize,
+            ))
+        })
+        .collect()
+    }
+
+    /// The number of unspent notes held of each asset.
+    pub async fn note_count_by_asset(&self) -> anyhow::Result<BTreeMap<asset::Id, usize>> {
+        sqlx::query(
+            "SELECT asset_id, COUNT(*) AS count
+            FROM notes
+            WHERE height_spent IS NULL
+            GROUP BY asset_id",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok((
+                Id::try_from(row.get::<&[u8], _>("asset_id"))?,
+                row.get::<i64, _>("count") as usize,
+            ))
+        })
+        .collect()
+    }
+
+    /// Estimate, per asset, the fee required to consolidate all unspent notes of that asset into a
+    /// single note, given a fee rate charged per action.
+    ///
+    /// Assets held in at most one note need no consolidation and are omitted.
+    pub async fn sweep_cost_estimate(
+        &self,
+        fee_rate: u64,
+    ) -> anyhow::Result<BTreeMap<asset::Id, u64>> {
+        let mut note_counts = BTreeMap::<asset::Id, usize>::new();
+        for record in self.notes(false, None, None, 0, true).await? {
+            *note_counts.entry(record.note.asset_id()).or_default() += 1;
+        }
+
+        Ok(note_counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(asset_id, count)| (asset_id, consolidation_fee(count, fee_rate)))
+            .collect())
+    }
+
+    pub async fn quarantined_notes(&self) -> anyhow::Result<Vec<QuarantinedNoteRecord>> {
+        let result = sqlx::query_as::<_, QuarantinedNoteRecord>("SELECT * FROM quarantined_notes")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Nullifiers (and the commitments of the notes they spend) which were detected a second
+    /// time after their notes were already recorded as spent.
+    ///
+    /// These indicate a serious inconsistency in the scanned state, which a client may want to
+    /// surface for manual review or resolve by resyncing.
+    pub async fn quarantined_inconsistencies(
+        &self,
+    ) -> anyhow::Result<Vec<(Nullifier, note::Commitment)>> {
+        sqlx::query("SELECT nullifier, note_commitment FROM inconsistent_nullifiers")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok((
+                    Nullifier::try_from(row.get::<&[u8], _>("nullifier"))?,
+                    note::Commitment::try_from(row.get::<&[u8], _>("note_commitment"))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Our notes which a transaction we submitted was going to spend, but which were instead spent
+    /// by a different transaction, with their nullifiers and the hash of the transaction that
+    /// spent them.
+    ///
+    /// Unless the same spending key is in use by another client, this means someone else holds
+    /// the key, and the user should move their remaining funds to a new one.
+    pub async fn foreign_spends(
+        &self,
+    ) -> anyhow::Result<Vec<(Nullifier, note::Commitment, [u8; 32])>> {
+        sqlx::query("SELECT nullifier, note_commitment, tx_hash FROM foreign_spends")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok((
+                    Nullifier::try_from(row.get::<&[u8], _>("nullifier"))?,
+                    note::Commitment::try_from(row.get::<&[u8], _>("note_commitment"))?,
+                    row.get::<&[u8], _>("tx_hash").try_into()?,
+                ))
+            })
+            .collect()
+    }
+
+    /// The assets the user has hidden from balance views.
+    pub async fn hidden_assets(&self) -> anyhow::Result<Vec<asset::Id>> {
+        sqlx::query("SELECT asset_id FROM hidden_assets")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| Id::try_from(row.get::<&[u8], _>("asset_id")))
+            .collect()
+    }
+
+    /// The application-specific value stored under `key` with [`Storage::set_metadata`], if any.
+    pub async fn metadata(&self, key: &str) -> anyhow::Result<Option<serde_json::Value>> {
+        sqlx::query("SELECT value FROM metadata WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| serde_json::from_str(row.get::<&str, _>("value")))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Store an application-specific value (e.g. a display currency or address labels) under
+    /// `key`, replacing any value already stored under it.
+    ///
+    /// The values are opaque to the view service. Their total size, with their keys, is limited
+    /// to [`MAX_METADATA_BYTES`].
+    pub async fn set_metadata(&self, key: &str, value: &serde_json::Value) -> anyhow::Result<()> {
+        let value = serde_json::to_string(value)?;
+        let mut dbtx = self.pool.begin().await?;
+
+        let others = sqlx::query(
+            "SELECT COALESCE(SUM(LENGTH(CAST(key AS BLOB)) + LENGTH(CAST(value AS BLOB))), 0)
+            AS size
+            FROM metadata
+            WHERE key != ?",
+        )
+        .bind(key)
+        .fetch_one(&mut dbtx)
+        .await?
+        .get::<i64, _>("size") as usize;
+        check_metadata_size(others, key.len() + value.len())?;
+
+        sqlx::query("INSERT OR REPLACE INTO metadata (key, value) VALUES (?, ?)")
+            .bind(key)
+            .bind(value)
+            .execute(&mut dbtx)
+            .await?;
+        dbtx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Remove the application-specific value stored under `key`, if any.
+    pub async fn remove_metadata(&self, key: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM metadata WHERE key = ?")
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Hide an asset (e.g. a spam airdrop) from balance views, without forgetting its notes.
+    pub async fn hide_asset(&self, asset_id: asset::Id) -> anyhow::Result<()> {
+        sqlx::query("INSERT OR IGNORE INTO hidden_assets (asset_id) VALUES (?)")
+            .bind(asset_id.to_bytes().to_vec())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stop hiding an asset previously hidden with [`Storage::hide_asset`].
+    pub async fn unhide_asset(&self, asset_id: asset::Id) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM hidden_assets WHERE asset_id = ?")
+            .bind(asset_id.to_bytes().to_vec())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The assets (e.g. vouchers) whose notes are not selected for spending.
+    pub async fn non_spendable_assets(&self) -> anyhow::Result<Vec<asset::Id>> {
+        sqlx::query("SELECT asset_id FROM non_spendable_assets")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| Id::try_from(row.get::<&[u8], _>("asset_id")))
+            .collect()
+    }
+
+    /// Whether notes of the given asset may be selected for spending.
+    pub async fn is_asset_spendable(&self, asset_id: asset::Id) -> anyhow::Result<bool> {
+        let row =
+            sqlx::query("SELECT EXISTS(SELECT 1 FROM non_spendable_assets WHERE asset_id = ?)")
+                .bind(asset_id.to_bytes().to_vec())
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(row.get::<i64, _>(0) == 0)
+    }
+
+    /// Allow or disallow selecting notes of the given asset for spending. Notes of non-spendable
+    /// assets still count towards balances.
+    pub async fn set_asset_spendable(
+        &self,
+        asset_id: asset::Id,
+        spendable: bool,
+    ) -> anyhow::Result<()> {
+        let query = match spendable {
+            true => "DELETE FROM non_spendable_assets WHERE asset_id = ?",
+            false => "INSERT OR IGNORE INTO non_spendable_assets (asset_id) VALUES (?)",
+        };
+        sqlx::query(query)
+            .bind(asset_id.to_bytes().to_vec())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Prune notes spent before `height`, along with the transactions that spent them, returning
+    /// the number of notes pruned.
+    ///
+    /// The nullifiers of pruned notes are retained, so that they are still recognized as ours by
+    /// [`Storage::is_retired_nullifier`], as are their assets, which remain in
+    /// [`Storage::known_denoms`]. Transactions which created a note that is still tracked are
+    /// kept.
+    pub async fn prune_spent_before(&self, height: u64) -> anyhow::Result<u64> {
+        let height = height as i64;
+        let mut dbtx = self.pool.begin().await?;
+
+        sqlx::query(
+            "INSERT OR IGNORE INTO retired_nullifiers (nullifier)
+            SELECT nullifier FROM notes WHERE height_spent < ?",
+        )
+        .bind(height)
+        .execute(&mut dbtx)
+        .await?;
+
+        sqlx::query(
+            "INSERT OR IGNORE INTO pruned_note_assets (asset_id)
+            SELECT asset_id FROM notes WHERE height_spent < ?",
+        )
+        .bind(height)
+        .execute(&mut dbtx)
+        .await?;
+
+        sqlx::query(
+            "DELETE FROM tx
+            WHERE tx_hash IN (
+                SELECT tx_hash FROM tx_by_nullifier
+                WHERE nullifier IN (SELECT nullifier FROM notes WHERE height_spent < ?)
+            )
+            AND tx_hash NOT IN (
+                SELECT source FROM notes WHERE height_spent IS NULL OR height_spent >= ?
+            )",
+        )
+        .bind(height)
+        .bind(height)
+        .execute(&mut dbtx)
+        .await?;
+
+        sqlx::query(
+            "DELETE FROM tx_by_nullifier
+            WHERE nullifier IN (SELECT nullifier FROM notes WHERE height_spent < ?)",
+        )
+        .bind(height)
+        .execute(&mut dbtx)
+        .await?;
+
+        let pruned = sqlx::query("DELETE FROM notes WHERE height_spent < ?")
+            .bind(height)
+            .execute(&mut dbtx)
+            .await?
+            .rows_affected();
+
+        dbtx.commit().await?;
+
+        Ok(pruned)
+    }
+
+    /// Replace the notes spent before `height` with [`SpentNoteTombstone`]s, dropping their
+    /// plaintext but keeping their values, and return the number of notes tombstoned.
+    ///
+    /// Like [`Storage::prune_spent_before`], this retires the nullifiers of the tombstoned notes,
+    /// but it keeps the transactions that spent them.
+    pub async fn tombstone_spent_before(&self, height: u64) -> anyhow::Result<u64> {
+        let mut dbtx = self.pool.begin().await?;
+
+        let spent = sqlx::query_as::<_, NoteRecord>("SELECT * FROM notes WHERE height_spent < ?")
+            .bind(height as i64)
+            .fetch_all(&mut dbtx)
+            .await?;
+
+        for tombstone in spent.iter().filter_map(SpentNoteTombstone::for_spent) {
+            let note_commitment = tombstone.note_commitment.0.to_bytes().to_vec();
+            let nullifier = tombstone.nullifier.0.to_bytes().to_vec();
+
+            sqlx::query(
+                "INSERT OR REPLACE INTO spent_note_tombstones
+                    (note_commitment, nullifier, amount, asset_id, height_created, height_spent)
+                VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(note_commitment.clone())
+            .bind(nullifier.clone())
+            .bind(tombstone.value.amount as i64)
+            .bind(tombstone.value.asset_id.to_bytes().to_vec())
+            .bind(tombstone.height_created as i64)
+            .bind(tombstone.height_spent as i64)
+            .execute(&mut dbtx)
+            .await?;
+
+            sqlx::query("INSERT OR IGNORE INTO retired_nullifiers (nullifier) VALUES (?)")
+                .bind(nullifier)
+                .execute(&mut dbtx)
+                .await?;
+
+            sqlx::query("DELETE FROM notes WHERE note_commitment = ?")
+                .bind(note_commitment)
+                .execute(&mut dbtx)
+                .await?;
+        }
+
+        dbtx.commit().await?;
+
+        Ok(spent.len() as u64)
+    }
+
+    /// The tombstones of spent notes whose plaintext was dropped by
+    /// [`Storage::tombstone_spent_before`].
+    pub async fn spent_note_tombstones(&self) -> anyhow::Result<Vec<SpentNoteTombstone>> {
+        sqlx::query("SELECT * FROM spent_note_tombstones ORDER BY height_spent")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(SpentNoteTombstone {
+                    note_commitment: note::Commitment::try_from(
+                        row.get::<&[u8], _>("note_commitment"),
+                    )?,
+                    nullifier: Nullifier::try_from(row.get::<&[u8], _>("nullifier"))?,
+                    value: Value {
+                        amount: row.get::<i64, _>("amount") as u64,
+                        asset_id: Id::try_from(row.get::<&[u8], _>("asset_id"))?,
+                    },
+                    height_created: row.get::<i64, _>("height_created") as u64,
+                    height_spent: row.get::<i64, _>("height_spent") as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// Record that we submitted the transaction with the given hash, which can be included in a
+    /// block at heights up to `expiry_height`, so that its progress can be followed with
+    /// [`Storage::transaction_state`].
+    pub async fn record_pending_transaction(
+        &self,
+        tx_hash: [u8; 32],
+        expiry_height: u64,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO pending_transactions (tx_hash, expiry_height) VALUES (?, ?)",
+        )
+        .bind(tx_hash.to_vec())
+        .bind(expiry_height as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record that we submitted the transaction with the given hash, built from `plan`, so that
+    /// the notes it spends are no longer returned as unspent and the change it returns to us is
+    /// reported by [`Storage::pending_change`] until the transaction is confirmed or fails.
+    pub async fn record_pending_plan(
+        &self,
+        tx_hash: [u8; 32],
+        plan: &TransactionPlan,
+    ) -> anyhow::Result<()> {
+        let fvk = self.full_viewing_key().await?;
+        let mut dbtx = self.pool.begin().await?;
+
+        sqlx::query(
+            "INSERT OR REPLACE INTO pending_transactions (tx_hash, expiry_height) VALUES (?, ?)",
         )
-        .fetch_all(&self.pool)
+        .bind(tx_hash.to_vec())
+        .bind(plan.expiry_height as i64)
+        .execute(&mut dbtx)
         .await?;
 
-        let mut output: Vec<Asset> = Vec::new();
+        for spend in plan.spend_plans() {
+            sqlx::query(
+                "INSERT OR REPLACE INTO pending_spends (note_commitment, tx_hash) VALUES (?, ?)",
+            )
+            .bind(spend.note.commit().0.to_bytes().to_vec())
+            .bind(tx_hash.to_vec())
+            .execute(&mut dbtx)
+            .await?;
+        }
 
-        for record in result {
-            let asset = Asset {
-                id: Id::try_from(record.asset_id.as_slice())?,
-                denom: asset::REGISTRY
-                    .parse_denom(&record.denom)
-                    .ok_or_else(|| anyhow::anyhow!("invalid denomination {}", record.denom))?,
-            };
-            output.push(asset);
+        for output in plan
+            .output_plans()
+            .filter(|output| fvk.incoming().views_address(&output.dest_address))
+        {
+            sqlx::query(
+                "INSERT OR REPLACE INTO pending_change (note_commitment, tx_hash, asset_id, amount)
+                VALUES (?, ?, ?, ?)",
+            )
+            .bind(output.output_note().commit().0.to_bytes().to_vec())
+            .bind(tx_hash.to_vec())
+            .bind(output.value.asset_id.to_bytes().to_vec())
+            .bind(output.value.amount as i64)
+            .execute(&mut dbtx)
+            .await?;
         }
 
-        Ok(output)
-    }
+        dbtx.commit().await?;
 
-    pub async fn notes(
-        &self,
-        include_spent: bool,
-        asset_id: Option<asset::Id>,
-        address_index: Option<penumbra_crypto::keys::AddressIndex>,
-        amount_to_spend: u64,
-    ) -> anyhow::Result<Vec<NoteRecord>> {
-        // If set, return spent notes as well as unspent notes.
-        // bool include_spent = 2;
-        let spent_clause = match include_spent {
-            false => "NULL",
-            true => "height_spent",
-        };
+        Ok(())
+    }
 
-        // If set, only return notes with the specified asset id.
-        // crypto.AssetId asset_id = 3;
+    /// The commitments and values of the notes we expect to receive from submitted transactions
+    /// which haven't yet been confirmed, recorded with [`Storage::record_pending_plan`].
+    pub async fn pending_change(&self) -> anyhow::Result<Vec<(note::Commitment, Value)>> {
+        sqlx::query("SELECT note_commitment, asset_id, amount FROM pending_change")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| -> anyhow::Result<_> {
+                Ok((
+                    note::Commitment::try_from(row.get::<&[u8], _>("note_commitment"))?,
+                    Value {
+                        amount: row.get::<i64, _>("amount") as u64,
+                        asset_id: asset::Id::try_from(row.get::<&[u8], _>("asset_id"))?,
+                    },
+                ))
+            })
+            .collect()
+    }
 
-        let asset_clause = asset_id
-            .map(|id| format!("x'{}'", hex::encode(&id.to_bytes())))
-            .unwrap_or_else(|| "asset_id".to_string());
+    /// The transactions recorded while scanning, with the values each sent to us and spent from
+    /// us, sorted by height.
+    ///
+    /// A transaction's height is found from the notes it created or spent, so transactions whose
+    /// notes were all pruned are omitted; tombstoned spent notes still count.
+    pub async fn transaction_history(&self) -> anyhow::Result<Vec<TransactionRecord>> {
+        let tx_hash = |row: &sqlx::sqlite::SqliteRow| -> anyhow::Result<[u8; 32]> {
+            row.get::<&[u8], _>("tx_hash")
+                .try_into()
+                .map_err(|_| anyhow!("invalid transaction hash"))
+        };
+        let value_at_height = |row: sqlx::sqlite::SqliteRow| -> anyhow::Result<_> {
+            Ok((
+                tx_hash(&row)?,
+                row.get::<i64, _>("height") as u64,
+                Value {
+                    amount: row.get::<i64, _>("amount") as u64,
+                    asset_id: Id::try_from(row.get::<&[u8], _>("asset_id"))?,
+                },
+            ))
+        };
 
-        // If set, only return notes with the specified address index.
-        // crypto.AddressIndex address_index = 4;
-        let address_clause = address_index
-            .map(|d| format!("x'{}'", hex::encode(&d.to_bytes())))
-            .unwrap_or_else(|| "address_index".to_string());
+        let transactions = sqlx::query("SELECT tx_hash, tx_bytes FROM tx")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| Ok((tx_hash(&row)?, row.get::<Vec<u8>, _>("tx_bytes"))))
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
-        let result = sqlx::query_as::<_, NoteRecord>(
-            format!(
-                "SELECT *
+        let received = sqlx::query(
+            "SELECT source AS tx_hash, height_created AS height, amount, asset_id
             FROM notes
-            WHERE height_spent IS {}
-            AND asset_id IS {}
-            AND address_index IS {}",
-                spent_clause, asset_clause, address_clause
-            )
-            .as_str(),
+            WHERE source IN (SELECT tx_hash FROM tx)",
         )
         .fetch_all(&self.pool)
-        .await?;
+        .await?
+        .into_iter()
+        .map(value_at_height)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let spent = sqlx::query(
+            "SELECT tx_by_nullifier.tx_hash, spent.height, spent.amount, spent.asset_id
+            FROM tx_by_nullifier
+            JOIN (
+                SELECT nullifier, height_spent AS height, amount, asset_id
+                FROM notes WHERE height_spent IS NOT NULL
+                UNION ALL
+                SELECT nullifier, height_spent AS height, amount, asset_id
+                FROM spent_note_tombstones
+            ) AS spent ON spent.nullifier = tx_by_nullifier.nullifier",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(value_at_height)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(transaction_record::transaction_history(
+            transactions,
+            received,
+            spent,
+        ))
+    }
 
-        // If set, stop returning notes once the total exceeds this amount.
-        //
-        // Ignored if `asset_id` is unset or if `include_spent` is set.
-        // uint64 amount_to_spend = 5;
-        //TODO: figure out a clever way to only return notes up to the sum using SQL
-        let amount_cutoff = (amount_to_spend != 0) && !(include_spent || asset_id.is_none());
-        let mut amount_total = 0;
-
-        let mut output: Vec<NoteRecord> = Vec::new();
-
-        for record in result.into_iter() {
-            let amount = record.note.amount();
-            output.push(record);
-            // If we're tracking amounts, accumulate the value of the note
-            // and check if we should break out of the loop.
-            if amount_cutoff {
-                // We know all the notes are of the same type, so adding raw quantities makes sense.
-                amount_total += amount;
-                if amount_total >= amount_to_spend {
-                    break;
-                }
-            }
+    /// The state of a transaction recorded with [`Storage::record_pending_transaction`], or `None`
+    /// if it is unknown.
+    ///
+    /// Since scanning fetches every transaction spending our notes, a submitted transaction is
+    /// confirmed once it has been recorded by scanning the block that included it.
+    pub async fn transaction_state(
+        &self,
+        tx_hash: [u8; 32],
+    ) -> anyhow::Result<Option<TransactionState>> {
+        let confirmed = sqlx::query("SELECT EXISTS(SELECT 1 FROM tx WHERE tx_hash = ?)")
+            .bind(tx_hash.to_vec())
+            .fetch_one(&self.pool)
+            .await?
+            .get::<i64, _>(0)
+            != 0;
+        if confirmed {
+            return Ok(Some(TransactionState::Confirmed));
         }
 
-        if amount_total < amount_to_spend {
-            return Err(anyhow!(
-                "requested amount of {} exceeds total of {}",
-                amount_to_spend,
-                amount_total
-            ));
-        }
+        let expiry_height =
+            sqlx::query("SELECT expiry_height FROM pending_transactions WHERE tx_hash = ?")
+                .bind(tx_hash.to_vec())
+                .fetch_optional(&self.pool)
+                .await?
+                .map(|row| row.get::<i64, _>("expiry_height") as u64);
 
-        Ok(output)
+        let expiry_height = match expiry_height {
+            Some(expiry_height) => expiry_height,
+            None => return Ok(None),
+        };
+
+        // An expiry height of zero means the transaction never expires.
+        let sync_height = self.last_sync_height().await?.unwrap_or(0);
+        if expiry_height != 0 && sync_height > expiry_height {
+            Ok(Some(TransactionState::Failed))
+        } else {
+            Ok(Some(TransactionState::Pending))
+        }
     }
 
-    pub async fn quarantined_notes(&self) -> anyhow::Result<Vec<QuarantinedNoteRecord>> {
-        let result = sqlx::query_as::<_, QuarantinedNoteRecord>("SELECT * FROM quarantined_notes")
+    /// The hashes of transactions which only paid ourselves, with the heights they were included
+    /// at, as detected while scanning.
+    pub async fn self_sends(&self) -> anyhow::Result<Vec<([u8; 32], u64)>> {
+        sqlx::query("SELECT tx_hash, height FROM self_sends ORDER BY height")
             .fetch_all(&self.pool)
-            .await?;
+            .await?
+            .into_iter()
+            .map(|row| {
+                let tx_hash = row
+                    .get::<&[u8], _>("tx_hash")
+                    .try_into()
+                    .map_err(|_| anyhow!("transaction hash is not 32 bytes"))?;
+                Ok((tx_hash, row.get::<i64, _>("height") as u64))
+            })
+            .collect()
+    }
 
-        Ok(result)
+    /// Whether the nullifier belongs to a spent note pruned with [`Storage::prune_spent_before`].
+    pub async fn is_retired_nullifier(&self, nullifier: &Nullifier) -> anyhow::Result<bool> {
+        let row =
+            sqlx::query("SELECT EXISTS(SELECT 1 FROM retired_nullifiers WHERE nullifier = ?)")
+                .bind(nullifier.to_bytes().to_vec())
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(row.get::<i64, _>(0) != 0)
     }
 
     pub async fn record_asset(&self, asset: Asset) -> anyhow::Result<()> {
@@ -449,15 +1920,10 @@ impl Storage {
             anyhow::anyhow!("invalid: tried to record empty block as genesis block")
         })?;
 
-        if height != last_sync_height + 1 {
-            return Err(anyhow::anyhow!(
-                "Wrong block height {} for latest sync height {}",
-                height,
-                last_sync_height
-            ));
-        }
+        check_block_height(Some(last_sync_height), height)?;
 
         *self.uncommitted_height.lock() = Some(height.try_into().unwrap());
+        *self.last_sync_time.lock() = Some(self.clock.now());
         Ok(())
     }
 
@@ -495,24 +1961,21 @@ impl Storage {
         filtered_block: FilteredBlock,
         transactions: Vec<Transaction>,
         nct: &mut tct::Tree,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<BlockScanResult> {
         //Check that the incoming block height follows the latest recorded height
         let last_sync_height = self.last_sync_height().await?;
 
-        let correct_height = match last_sync_height {
-            // Require that the new block follows the last one we scanned.
-            Some(cur_height) => filtered_block.height == cur_height + 1,
-            // Require that the new block represents the initial chain state.
-            None => filtered_block.height == 0,
-        };
+        check_block_height(last_sync_height, filtered_block.height)?;
 
-        if !correct_height {
-            return Err(anyhow::anyhow!(
-                "Wrong block height {} for latest sync height {:?}",
-                filtered_block.height,
-                last_sync_height
-            ));
+        // The nullifiers of notes we pruned were spent long ago, so they're expected to show up
+        // again when rescanning: they're skipped below rather than processed as new spends.
+        let mut retired_nullifiers = BTreeSet::new();
+        for nullifier in &filtered_block.spent_nullifiers {
+            if self.is_retired_nullifier(nullifier).await? {
+                retired_nullifiers.insert(*nullifier);
+            }
         }
+
         let mut dbtx = self.pool.begin().await?;
 
         // Insert all quarantined note commitments into storage
@@ -677,7 +2140,13 @@ impl Storage {
         }
 
         // Update any rows of the table with matching nullifiers to have height_spent
+        let mut spent_notes = Vec::new();
         for nullifier in &filtered_block.spent_nullifiers {
+            if retired_nullifiers.contains(nullifier) {
+                tracing::debug!(?nullifier, "skipping nullifier of a pruned note");
+                continue;
+            }
+
             // https://github.com/launchbadge/sqlx/issues/1430
             // https://github.com/launchbadge/sqlx/issues/1151
             // For some reason we can't use any temporaries with the query! macro
@@ -685,6 +2154,40 @@ impl Storage {
             // https://github.com/penumbra-zone/penumbra/blob/e857a7ae2b11b36514a5ac83f8e0b174fa10a65f/pd/src/state/writer.rs#L201-L207
             let height_spent = filtered_block.height as i64;
             let nullifier = nullifier.to_bytes().to_vec();
+
+            // If we already recorded the note as spent (and the spend wasn't merely quarantined),
+            // seeing its nullifier again indicates a serious inconsistency, such as a block being
+            // scanned twice: set it aside for review rather than overwriting the original spend.
+            let already_spent = sqlx::query(
+                "SELECT note_commitment
+                FROM notes
+                WHERE nullifier = ?
+                AND height_spent IS NOT NULL
+                AND nullifier NOT IN (SELECT nullifier FROM quarantined_nullifiers)",
+            )
+            .bind(&nullifier)
+            .fetch_optional(&mut dbtx)
+            .await?;
+
+            if let Some(row) = already_spent {
+                let note_commitment = row.get::<Vec<u8>, _>("note_commitment");
+                tracing::error!(
+                    nullifier = ?hex::encode(&nullifier),
+                    note_commitment = ?hex::encode(&note_commitment),
+                    "found known nullifier for a note that was already spent"
+                );
+                sqlx::query(
+                    "INSERT OR IGNORE INTO inconsistent_nullifiers (nullifier, note_commitment, height)
+                    VALUES (?, ?, ?)",
+                )
+                .bind(&nullifier)
+                .bind(&note_commitment)
+                .bind(height_spent)
+                .execute(&mut dbtx)
+                .await?;
+                continue;
+            }
+
             let spent_commitment_bytes = sqlx::query!(
                 "UPDATE notes SET height_spent = ? WHERE nullifier = ? RETURNING note_commitment",
                 height_spent,
@@ -697,6 +2200,19 @@ impl Storage {
                 // Forget spent note commitments from the NCT
                 let spent_commitment = Commitment::try_from(bytes.note_commitment.as_slice())?;
                 nct.forget(spent_commitment);
+
+                let row =
+                    sqlx::query("SELECT amount, asset_id FROM notes WHERE note_commitment = ?")
+                        .bind(&bytes.note_commitment)
+                        .fetch_one(&mut dbtx)
+                        .await?;
+                spent_notes.push((
+                    spent_commitment,
+                    Value {
+                        amount: row.get::<i64, _>("amount") as u64,
+                        asset_id: Id::try_from(row.get::<&[u8], _>("asset_id"))?,
+                    },
+                ));
             }
 
             // If the nullifier was previously quarantined, remove it from the list of quarantined
@@ -748,6 +2264,15 @@ impl Storage {
         // Update NCT table with current NCT state
         nct.serialize(&mut TreeStore(&mut dbtx)).await?;
 
+        // Record any transactions which only paid ourselves
+        for tx_hash in filtered_block.self_send_transaction_ids(&transactions) {
+            sqlx::query("INSERT OR IGNORE INTO self_sends (tx_hash, height) VALUES (?, ?)")
+                .bind(tx_hash.to_vec())
+                .bind(filtered_block.height as i64)
+                .execute(&mut dbtx)
+                .await?;
+        }
+
         // Record all transactions
         for transaction in transactions {
             let tx_bytes = transaction.encode_to_vec();
@@ -778,6 +2303,69 @@ impl Storage {
             }
         }
 
+        // Reconcile pending transactions: once a pending spend is seen, or a pending change note
+        // is received, it's no longer pending. Once a pending transaction expires without being
+        // included, its spends are returned to the unspent notes and its change won't arrive.
+        //
+        // If a pending spend's note was spent by some other transaction than the one we submitted,
+        // the spending key may be compromised: record it for the user to review.
+        let foreign = sqlx::query(
+            "INSERT OR IGNORE INTO foreign_spends (note_commitment, nullifier, tx_hash, height)
+            SELECT notes.note_commitment, notes.nullifier, tx_by_nullifier.tx_hash, notes.height_spent
+            FROM pending_spends
+            JOIN notes ON notes.note_commitment = pending_spends.note_commitment
+            JOIN tx_by_nullifier ON tx_by_nullifier.nullifier = notes.nullifier
+            WHERE notes.height_spent IS NOT NULL
+            AND tx_by_nullifier.tx_hash != pending_spends.tx_hash",
+        )
+        .execute(&mut dbtx)
+        .await?;
+        if foreign.rows_affected() > 0 {
+            tracing::error!(
+                count = foreign.rows_affected(),
+                height = filtered_block.height,
+                "notes we submitted spends of were spent by a different transaction"
+            );
+        }
+        sqlx::query(
+            "DELETE FROM pending_spends
+            WHERE note_commitment IN (
+                SELECT note_commitment FROM notes WHERE height_spent IS NOT NULL
+            )",
+        )
+        .execute(&mut dbtx)
+        .await?;
+        sqlx::query(
+            "DELETE FROM pending_change
+            WHERE note_commitment IN (SELECT note_commitment FROM notes)",
+        )
+        .execute(&mut dbtx)
+        .await?;
+        // Reservations end once their notes are spent, or lapse if the send never made it on chain.
+        sqlx::query(
+            "DELETE FROM reserved_notes
+            WHERE note_commitment IN (
+                SELECT note_commitment FROM notes WHERE height_spent IS NOT NULL
+            )
+            OR (expiry_height != 0 AND expiry_height < ?)",
+        )
+        .bind(filtered_block.height as i64)
+        .execute(&mut dbtx)
+        .await?;
+        for table in ["pending_spends", "pending_change"] {
+            sqlx::query(&format!(
+                "DELETE FROM {}
+                WHERE tx_hash IN (
+                    SELECT tx_hash FROM pending_transactions
+                    WHERE expiry_height != 0 AND expiry_height < ?
+                )",
+                table
+            ))
+            .bind(filtered_block.height as i64)
+            .execute(&mut dbtx)
+            .await?;
+        }
+
         // Record block height as latest synced height
 
         let latest_sync_height = filtered_block.height as i64;
@@ -789,6 +2377,7 @@ impl Storage {
         // It's critical to reset the uncommitted height here, since we've just
         // invalidated it by committing.
         self.uncommitted_height.lock().take();
+        *self.last_sync_time.lock() = Some(self.clock.now());
 
         // Broadcast all committed note records to channel
         // Done following tx.commit() to avoid notifying of a new NoteRecord before it is actually committed to the database
@@ -810,6 +2399,966 @@ impl Storage {
             let _ = self.scanned_nullifiers_tx.send(*nullifier);
         }
 
+        Ok(BlockScanResult {
+            height: filtered_block.height,
+            new_notes: filtered_block.new_notes,
+            spent_notes,
+        })
+    }
+}
+
+/// The query for the notes returned by [`Storage::notes`], before any amount cutoff.
+fn notes_query(
+    include_spent: bool,
+    asset_id: Option<asset::Id>,
+    address_index: Option<AddressIndex>,
+    include_hidden: bool,
+) -> String {
+    // If set, return spent notes as well as unspent notes.
+    // bool include_spent = 2;
+    let spent_clause = match include_spent {
+        false => "NULL",
+        true => "height_spent",
+    };
+
+    // If set, only return notes with the specified asset id.
+    // crypto.AssetId asset_id = 3;
+
+    let asset_clause = asset_id
+        .map(|id| format!("x'{}'", hex::encode(&id.to_bytes())))
+        .unwrap_or_else(|| "asset_id".to_string());
+
+    // If set, only return notes with the specified address index.
+    // crypto.AddressIndex address_index = 4;
+    let address_clause = address_index
+        .map(|d| format!("x'{}'", hex::encode(&d.to_bytes())))
+        .unwrap_or_else(|| "address_index".to_string());
+
+    // Unless requested, skip notes of assets the user has hidden.
+    let hidden_clause = match include_hidden {
+        false => "AND asset_id NOT IN (SELECT asset_id FROM hidden_assets)",
+        true => "",
+    };
+
+    // Notes reserved for an in-progress send, or spent by a submitted transaction, are not
+    // considered unspent.
+    let reserved_clause = match include_spent {
+        false => {
+            "AND note_commitment NOT IN (SELECT note_commitment FROM reserved_notes)
+            AND note_commitment NOT IN (SELECT note_commitment FROM pending_spends)"
+        }
+        true => "",
+    };
+
+    format!(
+        "SELECT *
+        FROM notes
+        WHERE height_spent IS {}
+        AND asset_id IS {}
+        AND address_index IS {}
+        {}
+        {}",
+        spent_clause, asset_clause, address_clause, hidden_clause, reserved_clause
+    )
+}
+
+/// The leading `notes` whose total first reaches `amount_to_spend`, or all of them if
+/// `amount_cutoff` isn't set, failing if they don't reach it.
+fn notes_covering(
+    notes: Vec<NoteRecord>,
+    amount_cutoff: bool,
+    amount_to_spend: u64,
+) -> anyhow::Result<Vec<NoteRecord>> {
+    let amount_cutoff = amount_cutoff && amount_to_spend != 0;
+    let mut amount_total = 0;
+
+    let mut output: Vec<NoteRecord> = Vec::new();
+
+    for record in notes.into_iter() {
+        let amount = record.note.amount();
+        output.push(record);
+        // If we're tracking amounts, accumulate the value of the note
+        // and check if we should break out of the loop.
+        if amount_cutoff {
+            // We know all the notes are of the same type, so adding raw quantities makes sense.
+            amount_total += amount;
+            if amount_total >= amount_to_spend {
+                break;
+            }
+        }
+    }
+
+    if amount_total < amount_to_spend {
+        return Err(anyhow!(
+            "requested amount of {} exceeds total of {}",
+            amount_to_spend,
+            amount_total
+        ));
+    }
+
+    Ok(output)
+}
+
+/// The fee to consolidate `note_count` notes into one: a spend for each note, plus one output.
+fn consolidation_fee(note_count: usize, fee_rate: u64) -> u64 {
+    (note_count as u64 + 1).saturating_mul(fee_rate)
+}
+
+/// The fragmentation score of notes with the given amounts; see [`Storage::fragmentation_score`].
+fn fragmentation_score(amounts: &[u64]) -> f64 {
+    let total = amounts.iter().map(|&amount| amount as f64).sum::<f64>();
+    let smallest = amounts.iter().copied().min().unwrap_or(0) as f64;
+    if amounts.len() <= 1 || total == 0.0 {
+        return 0.0;
+    }
+
+    let count_factor = 1.0 - 1.0 / amounts.len() as f64;
+    let smallest_factor = 1.0 - smallest / total;
+    100.0 * count_factor * smallest_factor
+}
+
+/// The denomination `denom` is compared by when checking for [`denom_collisions`].
+fn canonical_denom(denom: &str) -> String {
+    denom.to_lowercase()
+}
+
+/// Group the assets whose denominations are equal after canonicalization, returning only the
+/// groups with more than one asset; see [`Storage::denom_collisions`].
+fn denom_collisions(assets: Vec<Asset>) -> Vec<Vec<Asset>> {
+    let mut by_canonical_denom = BTreeMap::<String, Vec<Asset>>::new();
+    for asset in assets {
+        by_canonical_denom
+            .entry(canonical_denom(&asset.denom.to_string()))
+            .or_default()
+            .push(asset);
+    }
+
+    by_canonical_denom
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+/// Whether empty blocks up to `uncommitted_height` should be committed, given the committed
+/// height (negative before genesis); see [`Storage::checkpoint_if_due`].
+fn checkpoint_due(committed_height: i64, uncommitted_height: u64, interval: u64) -> bool {
+    uncommitted_height as i64 - committed_height >= interval as i64
+}
+
+/// The age of a note created at `height_created` as of `sync_height`, or `None` if the note was
+/// created after that height.
+fn note_age(height_created: u64, sync_height: u64) -> Option<u64> {
+    sync_height.checked_sub(height_created)
+}
+
+/// The smallest and largest amounts of each asset among `notes`; see [`Storage::note_extremes`].
+fn note_extremes(
+    notes: impl IntoIterator<Item = (asset::Id, u64)>,
+) -> BTreeMap<asset::Id, (u64, u64)> {
+    let mut extremes = BTreeMap::<asset::Id, (u64, u64)>::new();
+    for (asset_id, amount) in notes {
+        extremes
+            .entry(asset_id)
+            .and_modify(|(min, max)| {
+                *min = (*min).min(amount);
+                *max = (*max).max(amount);
+            })
+            .or_insert((amount, amount));
+    }
+    extremes
+}
+
+/// The `items` whose `amount` is at least `minimum`, in ascending order of amount; see
+/// [`Storage::notes_meeting_minimum`].
+fn meeting_minimum<T>(items: Vec<T>, minimum: u64, amount: impl Fn(&T) -> u64) -> Vec<T> {
+    let mut items = items
+        .into_iter()
+        .filter(|item| amount(item) >= minimum)
+        .collect::<Vec<_>>();
+    items.sort_by_key(|item| amount(item));
+    items
+}
+
+fn check_metadata_size(others: usize, entry: usize) -> anyhow::Result<()> {
+    if others + entry > MAX_METADATA_BYTES {
+        return Err(anyhow!(
+            "metadata would total {} bytes, exceeding the limit of {} bytes",
+            others + entry,
+            MAX_METADATA_BYTES
+        ));
+    }
+    Ok(())
+}
+
+fn balances_by_address(
+    denoms: &BTreeMap<asset::Id, String>,
+    notes: impl IntoIterator<Item = (AddressIndex, asset::Id, u64)>,
+) -> BTreeMap<AddressIndex, BTreeMap<String, u64>> {
+    let mut balances = BTreeMap::<AddressIndex, BTreeMap<String, u64>>::new();
+    for (address_index, asset_id, amount) in notes {
+        let denom = denoms
+            .get(&asset_id)
+            .cloned()
+            .unwrap_or_else(|| asset_id.to_string());
+        *balances
+            .entry(address_index)
+            .or_default()
+            .entry(denom)
+            .or_default() += amount;
+    }
+    balances
+}
+
+fn stale_denoms(
+    local: &BTreeMap<asset::Id, String>,
+    authoritative: &BTreeMap<asset::Id, String>,
+) -> BTreeSet<asset::Id> {
+    authoritative
+        .iter()
+        .filter(|(asset_id, denom)| local.get(asset_id) != Some(denom))
+        .map(|(asset_id, _)| *asset_id)
+        .collect()
+}
+
+/// Check that a block at `height` directly follows the last block we scanned, or represents the
+/// initial chain state if we haven't scanned any.
+fn check_block_height(
+    last_sync_height: Option<u64>,
+    height: u64,
+) -> Result<(), UnexpectedBlockHeight> {
+    let expected = last_sync_height.map_or(0, |last| last + 1);
+    if height == expected {
+        Ok(())
+    } else {
+        Err(UnexpectedBlockHeight {
+            expected,
+            got: height,
+        })
+    }
+}
+
+/// Push `item`, if any, to the back of `items`, then drop items from the front until at most
+/// `max` remain.
+fn push_bounded<T>(items: &mut VecDeque<T>, item: Option<T>, max: usize) {
+    items.extend(item);
+    while items.len() > max {
+        items.pop_front();
+    }
+}
+
+fn verify_anchor(
+    recent_anchors: &VecDeque<(u64, tct::Root)>,
+    height: u64,
+    expected: tct::Root,
+) -> Result<(), AnchorMismatch> {
+    let actual = recent_anchors
+        .iter()
+        .find(|(anchor_height, _)| *anchor_height == height)
+        .map(|(_, root)| *root);
+
+    if actual == Some(expected) {
         Ok(())
+    } else {
+        Err(AnchorMismatch {
+            height,
+            expected,
+            actual,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use penumbra_chain::NoteSource;
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        memo::MemoPlaintext,
+    };
+    use penumbra_transaction::{
+        plan::{OutputPlan, SpendPlan},
+        WitnessData,
+    };
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::test_utils::{filtered_block, note_record, record_notes, temp_storage};
+
+    #[test]
+    fn consolidation_fee_scales_with_note_count() {
+        assert_eq!(consolidation_fee(2, 10), 30);
+        assert_eq!(consolidation_fee(8, 10), 90);
+        assert!(consolidation_fee(20, 10) > consolidation_fee(10, 10));
+    }
+
+    #[test]
+    fn fragmentation_score_grows_with_tiny_notes() {
+        assert_eq!(fragmentation_score(&[1000]), 0.0);
+        assert!(fragmentation_score(&[1; 100]) > 90.0);
+        assert!(fragmentation_score(&[1, 1, 1000]) > fragmentation_score(&[500, 500]));
+    }
+
+    #[test]
+    fn note_age_is_blocks_since_creation() {
+        assert_eq!(note_age(90, 100), Some(10));
+        assert_eq!(note_age(100, 100), Some(0));
+        assert_eq!(note_age(101, 100), None);
+    }
+
+    #[test]
+    fn denom_collisions_group_case_variants() {
+        let asset = |denom: &str| {
+            let denom = asset::REGISTRY.parse_denom(denom).unwrap();
+            Asset {
+                id: denom.id(),
+                denom,
+            }
+        };
+
+        let collisions = denom_collisions(vec![asset("ugm"), asset("upenumbra"), asset("uGM")]);
+        assert_eq!(collisions.len(), 1);
+        let mut denoms = collisions[0]
+            .iter()
+            .map(|asset| asset.denom.to_string())
+            .collect::<Vec<_>>();
+        denoms.sort();
+        assert_eq!(denoms, vec!["uGM", "ugm"]);
+    }
+
+    #[test]
+    fn checkpoint_is_due_after_interval() {
+        assert!(!checkpoint_due(10, 15, 10));
+        assert!(checkpoint_due(10, 20, 10));
+        assert!(checkpoint_due(-1, 9, 10));
+        assert!(!checkpoint_due(-1, 8, 10));
+    }
+
+    #[test]
+    fn note_extremes_are_per_asset() {
+        let other_id = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+        let extremes = note_extremes([
+            (*STAKING_TOKEN_ASSET_ID, 30),
+            (other_id, 7),
+            (*STAKING_TOKEN_ASSET_ID, 5),
+            (*STAKING_TOKEN_ASSET_ID, 120),
+        ]);
+
+        assert_eq!(extremes.get(&*STAKING_TOKEN_ASSET_ID), Some(&(5, 120)));
+        assert_eq!(extremes.get(&other_id), Some(&(7, 7)));
+    }
+
+    #[test]
+    fn minimum_filters_smaller_notes() {
+        let amounts = vec![50, 10, 200, 100, 99];
+        assert_eq!(meeting_minimum(amounts.clone(), 75, |a| *a), [99, 100, 200]);
+        assert_eq!(meeting_minimum(amounts.clone(), 100, |a| *a), [100, 200]);
+        assert!(meeting_minimum(amounts, 201, |a| *a).is_empty());
+    }
+
+    #[test]
+    fn metadata_size_is_capped() {
+        let value = serde_json::json!({ "currency": "USD", "labels": { "0": "savings" } });
+        let encoded = serde_json::to_string(&value).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&encoded).unwrap(),
+            value
+        );
+
+        assert!(check_metadata_size(0, encoded.len()).is_ok());
+        assert!(check_metadata_size(MAX_METADATA_BYTES - encoded.len(), encoded.len()).is_ok());
+        assert!(check_metadata_size(MAX_METADATA_BYTES - 1, encoded.len()).is_err());
+    }
+
+    #[test]
+    fn balances_are_split_by_address() {
+        let other_id = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+        let denoms = [(*STAKING_TOKEN_ASSET_ID, "upenumbra".to_string())]
+            .into_iter()
+            .collect();
+        let first = AddressIndex::from(0u64);
+        let second = AddressIndex::from(1u64);
+
+        let balances = balances_by_address(
+            &denoms,
+            [
+                (first, *STAKING_TOKEN_ASSET_ID, 30),
+                (second, *STAKING_TOKEN_ASSET_ID, 5),
+                (first, *STAKING_TOKEN_ASSET_ID, 12),
+                (second, other_id, 7),
+            ],
+        );
+
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[&first].get("upenumbra"), Some(&42));
+        assert_eq!(balances[&first].len(), 1);
+        assert_eq!(balances[&second].get("upenumbra"), Some(&5));
+        assert_eq!(balances[&second].get(&other_id.to_string()), Some(&7));
+    }
+
+    #[test]
+    fn changed_denoms_are_stale() {
+        let unchanged = asset::REGISTRY.parse_denom("upenumbra").unwrap().id();
+        let changed = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+        let unknown = asset::REGISTRY.parse_denom("ugn").unwrap().id();
+        let local = [
+            (unchanged, "upenumbra".to_string()),
+            (changed, "ugm".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let authoritative = [
+            (unchanged, "upenumbra".to_string()),
+            (changed, "ugmcorrected".to_string()),
+            (unknown, "ugn".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            stale_denoms(&local, &authoritative),
+            [changed, unknown].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn blocks_must_follow_last_synced_height() {
+        assert_eq!(check_block_height(None, 0), Ok(()));
+        assert_eq!(check_block_height(Some(9), 10), Ok(()));
+        assert_eq!(
+            check_block_height(Some(9), 12),
+            Err(UnexpectedBlockHeight {
+                expected: 10,
+                got: 12
+            })
+        );
+        assert_eq!(
+            check_block_height(None, 5),
+            Err(UnexpectedBlockHeight {
+                expected: 0,
+                got: 5
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rescanned_spend_of_pruned_note_is_skipped() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+
+        let record = note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 0, 0);
+        let mut nct = tct::Tree::new();
+        record_notes(&storage, &mut nct, 0, vec![record.clone()]).await;
+        storage
+            .record_block(
+                filtered_block(1, Vec::new(), vec![record.nullifier]),
+                Vec::new(),
+                &mut nct,
+            )
+            .await
+            .unwrap();
+        assert_eq!(storage.prune_spent_before(2).await.unwrap(), 1);
+        assert!(storage
+            .is_retired_nullifier(&record.nullifier)
+            .await
+            .unwrap());
+
+        // Seeing the nullifier again neither spends anything nor is flagged as inconsistent.
+        let result = storage
+            .record_block(
+                filtered_block(2, Vec::new(), vec![record.nullifier]),
+                Vec::new(),
+                &mut nct,
+            )
+            .await
+            .unwrap();
+        assert!(result.spent_notes.is_empty());
+        assert!(storage
+            .quarantined_inconsistencies()
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .notes(true, None, None, 0, true)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn tombstoned_and_pruned_notes_stay_known() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let gm = asset::REGISTRY.parse_denom("ugm").unwrap();
+        let gn = asset::REGISTRY.parse_denom("ugn").unwrap();
+        for denom in [&gm, &gn] {
+            storage
+                .record_asset(Asset {
+                    id: denom.id(),
+                    denom: denom.clone(),
+                })
+                .await
+                .unwrap();
+        }
+
+        let tombstoned = note_record(fvk, 0, 10, gm.id(), 0, 0);
+        let pruned = note_record(fvk, 0, 20, gn.id(), 1, 1);
+        let mut nct = tct::Tree::new();
+        record_notes(&storage, &mut nct, 0, vec![tombstoned.clone()]).await;
+        record_notes(&storage, &mut nct, 1, vec![pruned.clone()]).await;
+        storage
+            .record_block(
+                filtered_block(2, Vec::new(), vec![tombstoned.nullifier]),
+                Vec::new(),
+                &mut nct,
+            )
+            .await
+            .unwrap();
+        storage
+            .record_block(
+                filtered_block(3, Vec::new(), vec![pruned.nullifier]),
+                Vec::new(),
+                &mut nct,
+            )
+            .await
+            .unwrap();
+        let known = storage.known_denoms().await.unwrap();
+
+        assert_eq!(storage.tombstone_spent_before(3).await.unwrap(), 1);
+        assert_eq!(storage.prune_spent_before(4).await.unwrap(), 1);
+
+        assert!(storage
+            .tracks_commitment(tombstoned.note_commitment)
+            .await
+            .unwrap());
+        assert_eq!(storage.known_denoms().await.unwrap(), known);
+        assert!(known.contains(&gm.to_string()) && known.contains(&gn.to_string()));
+    }
+
+    #[tokio::test]
+    async fn block_spending_and_paying_only_us_is_a_self_send() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let (address, _dtk) = fvk.incoming().payment_address(1u64.into());
+
+        let record = note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 0, 0);
+        let mut nct = tct::Tree::new();
+        record_notes(&storage, &mut nct, 0, vec![record.clone()]).await;
+
+        // Spend the note, paying all of it back to another of our addresses.
+        let plan = TransactionPlan {
+            actions: vec![
+                SpendPlan::new(&mut OsRng, record.note.clone(), record.position).into(),
+                OutputPlan::new(
+                    &mut OsRng,
+                    record.note.value(),
+                    address,
+                    MemoPlaintext::default(),
+                )
+                .into(),
+            ],
+            ..Default::default()
+        };
+        let output = plan.output_plans().next().unwrap().output_note();
+        let auth_data = plan.authorize(OsRng, &sk);
+        let witness_data = WitnessData {
+            anchor: nct.root(),
+            note_commitment_proofs: vec![nct.witness(record.note_commitment).unwrap()],
+        };
+        let transaction = plan
+            .clone()
+            .build(&mut OsRng, fvk, auth_data, witness_data)
+            .unwrap();
+        storage
+            .record_pending_plan(transaction.id(), &plan)
+            .await
+            .unwrap();
+
+        let position = nct.insert(tct::Witness::Keep, output.commit()).unwrap();
+        let received = NoteRecord {
+            note_commitment: output.commit(),
+            nullifier: fvk.derive_nullifier(position, &output.commit()),
+            address_index: 1u64.into(),
+            height_created: 1,
+            height_spent: None,
+            position,
+            source: NoteSource::Transaction {
+                id: transaction.id(),
+            },
+            note: output,
+        };
+        let result = storage
+            .record_block(
+                filtered_block(1, vec![received], vec![record.nullifier]),
+                vec![transaction.clone()],
+                &mut nct,
+            )
+            .await
+            .unwrap();
+
+        assert!(result.balance_delta().values().all(|delta| *delta == 0));
+        assert_eq!(
+            storage.self_sends().await.unwrap(),
+            vec![(transaction.id(), 1)]
+        );
+        assert!(storage.foreign_spends().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn notes_of_non_spendable_assets_are_not_selected() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let asset_id = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+
+        let mut nct = tct::Tree::new();
+        let record = note_record(fvk, 0, 10, asset_id, 0, 0);
+        record_notes(&storage, &mut nct, 0, vec![record.clone()]).await;
+        storage.set_asset_spendable(asset_id, false).await.unwrap();
+
+        // The planner selects among all unspent notes itself, so it asks for them without an
+        // amount to spend; the selection is refused all the same.
+        assert!(storage.spendable_notes(asset_id, None, 0).await.is_err());
+        assert!(storage.spendable_notes(asset_id, None, 5).await.is_err());
+
+        storage.set_asset_spendable(asset_id, true).await.unwrap();
+        let selected = storage.spendable_notes(asset_id, None, 0).await.unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].note_commitment, record.note_commitment);
+    }
+
+    #[tokio::test]
+    async fn spending_non_spendable_asset_fails_while_balance_shows() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let asset_id = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![note_record(fvk, 0, 10, asset_id, 0, 0)],
+        )
+        .await;
+        storage.set_asset_spendable(asset_id, false).await.unwrap();
+
+        assert_eq!(
+            storage
+                .available_balance(&SpendPolicy::default())
+                .await
+                .unwrap()[&asset_id],
+            10
+        );
+        for error in [
+            storage
+                .spendable_notes(asset_id, None, 0)
+                .await
+                .unwrap_err(),
+            storage.begin_send(asset_id, 5).await.err().unwrap(),
+        ] {
+            assert_eq!(
+                error.downcast_ref::<AssetNonSpendable>(),
+                Some(&AssetNonSpendable { asset_id })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unspent_totals_include_reserved_notes() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let asset_id = *STAKING_TOKEN_ASSET_ID;
+
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![
+                note_record(fvk, 0, 10, asset_id, 0, 0),
+                note_record(fvk, 0, 20, asset_id, 0, 1),
+            ],
+        )
+        .await;
+        let _session = storage.begin_send(asset_id, 5).await.unwrap();
+
+        assert!(
+            storage
+                .available_balance(&SpendPolicy::default())
+                .await
+                .unwrap()[&asset_id]
+                < 30
+        );
+        assert_eq!(storage.unspent_totals().await.unwrap()[&asset_id], 30);
+    }
+
+    #[tokio::test]
+    async fn hidden_assets_are_left_out_of_balances_unless_requested() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let visible = *STAKING_TOKEN_ASSET_ID;
+        let hidden = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![
+                note_record(fvk, 0, 10, visible, 0, 0),
+                note_record(fvk, 0, 20, hidden, 0, 1),
+            ],
+        )
+        .await;
+        storage.hide_asset(hidden).await.unwrap();
+
+        let available = storage
+            .available_balance(&SpendPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(available.get(&visible), Some(&10));
+        assert_eq!(available.get(&hidden), None);
+        assert_eq!(storage.balances(false).await.unwrap().len(), 1);
+        assert_eq!(
+            storage.balances_by_address(false).await.unwrap()[&AddressIndex::from(0)].len(),
+            1
+        );
+
+        let available = storage
+            .available_balance(&SpendPolicy {
+                include_hidden: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(available.get(&hidden), Some(&20));
+        assert_eq!(storage.balances(true).await.unwrap().len(), 2);
+        assert_eq!(
+            storage.balances_by_address(true).await.unwrap()[&AddressIndex::from(0)].len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn change_if_spent_previews_change_of_selection() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let gm = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+
+        let small = note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 0, 0);
+        let large = note_record(fvk, 0, 20, *STAKING_TOKEN_ASSET_ID, 0, 1);
+        let other = note_record(fvk, 0, 5, gm, 0, 2);
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![small.clone(), large.clone(), other.clone()],
+        )
+        .await;
+
+        let both = [small.note_commitment, large.note_commitment];
+        assert_eq!(storage.change_if_spent(&both, 25, 3).await.unwrap(), 2);
+        // The fee is only paid out of the staking token.
+        assert_eq!(
+            storage
+                .change_if_spent(&[other.note_commitment], 4, 3)
+                .await
+                .unwrap(),
+            1
+        );
+
+        // A selection which doesn't cover the amount plus fee has no change to preview.
+        assert!(storage
+            .change_if_spent(&[small.note_commitment], 25, 3)
+            .await
+            .is_err());
+        assert!(storage.change_if_spent(&both, 28, 3).await.is_err());
+        assert!(storage
+            .change_if_spent(&[small.note_commitment, other.note_commitment], 1, 0)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn spend_policy_can_count_reserved_notes_and_pending_change() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+        let asset_id = *STAKING_TOKEN_ASSET_ID;
+
+        let mut nct = tct::Tree::new();
+        record_notes(
+            &storage,
+            &mut nct,
+            0,
+            vec![
+                note_record(fvk, 0, 10, asset_id, 0, 0),
+                note_record(fvk, 0, 20, asset_id, 0, 1),
+            ],
+        )
+        .await;
+        let available = |policy| {
+            let storage = storage.clone();
+            async move {
+                storage
+                    .available_balance(&policy)
+                    .await
+                    .unwrap()
+                    .get(&asset_id)
+                    .copied()
+                    .unwrap_or(0)
+            }
+        };
+
+        // Lock one of the notes in a send session.
+        let session = storage.begin_send(asset_id, 10).await.unwrap();
+        let locked = session.notes()[0].note.amount();
+        assert_eq!(available(SpendPolicy::default()).await, 30 - locked);
+        let with_reserved = SpendPolicy {
+            include_reserved: true,
+            ..Default::default()
+        };
+        assert_eq!(available(with_reserved).await, 30);
+
+        // Submit a plan spending the other note, returning change of 7.
+        let spent = storage
+            .notes(false, None, None, 0, true)
+            .await
+            .unwrap()
+            .remove(0);
+        let (address, _dtk) = fvk.incoming().payment_address(0u64.into());
+        let plan = TransactionPlan {
+            actions: vec![
+                SpendPlan::new(&mut OsRng, spent.note.clone(), spent.position).into(),
+                OutputPlan::new(
+                    &mut OsRng,
+                    Value {
+                        amount: 7,
+                        asset_id,
+                    },
+                    address,
+                    MemoPlaintext::default(),
+                )
+                .into(),
+            ],
+            ..Default::default()
+        };
+        storage.record_pending_plan([1; 32], &plan).await.unwrap();
+
+        let with_pending_change = SpendPolicy {
+            include_pending_change: true,
+            ..Default::default()
+        };
+        assert_eq!(available(with_reserved).await, locked);
+        assert_eq!(available(with_pending_change).await, 7);
+        assert_eq!(
+            available(SpendPolicy {
+                include_reserved: true,
+                include_pending_change: true,
+                ..Default::default()
+            })
+            .await,
+            locked + 7
+        );
+        assert_eq!(
+            available(SpendPolicy {
+                min_confirmations: 1,
+                ..with_pending_change
+            })
+            .await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn old_anchors_are_dropped_beyond_max() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let (_dir, storage) = temp_storage(sk.full_viewing_key()).await;
+        assert!(storage.clone().with_max_recent_anchors(0).is_err());
+        let storage = storage.with_max_recent_anchors(3).unwrap();
+
+        let mut nct = tct::Tree::new();
+        let mut anchors = Vec::new();
+        for height in 0..5 {
+            nct.end_block().unwrap();
+            storage.record_anchor(height, nct.root());
+            anchors.push((height, nct.root()));
+        }
+        assert_eq!(storage.recent_anchors(), anchors[2..]);
+
+        // Lowering the bound drops the oldest of the anchors already recorded.
+        let storage = storage.with_max_recent_anchors(1).unwrap();
+        assert_eq!(storage.recent_anchors(), anchors[4..]);
+    }
+
+    #[test]
+    fn bounded_history_keeps_most_recent() {
+        let mut items = VecDeque::new();
+        for i in 0..5 {
+            push_bounded(&mut items, Some(i), 3);
+        }
+        assert_eq!(items, [2, 3, 4]);
+
+        push_bounded(&mut items, None, 1);
+        assert_eq!(items, [4]);
+    }
+
+    #[test]
+    fn mismatched_anchor_is_detected() {
+        let mut nct = tct::Tree::new();
+        let before = nct.root();
+        nct.insert(tct::Witness::Forget, Commitment(1u64.into()))
+            .unwrap();
+        let after = nct.root();
+
+        let recent_anchors = [(5, before), (6, after)].into_iter().collect();
+        assert_eq!(verify_anchor(&recent_anchors, 6, after), Ok(()));
+        assert_eq!(
+            verify_anchor(&recent_anchors, 6, before),
+            Err(AnchorMismatch {
+                height: 6,
+                expected: before,
+                actual: Some(after),
+            })
+        );
+        assert_eq!(
+            verify_anchor(&recent_anchors, 7, after).unwrap_err().actual,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn load_or_initialize_verifies_nullifiers_when_asked() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (dir, storage) = temp_storage(fvk).await;
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("storage.sqlite")).unwrap();
+
+        // Store a note under a nullifier derived at the wrong position.
+        let mut record = note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 0, 0);
+        record.nullifier = fvk.derive_nullifier(1u64.into(), &record.note_commitment);
+        let mut nct = tct::Tree::new();
+        record_notes(&storage, &mut nct, 0, vec![record]).await;
+        drop(storage);
+
+        // An existing database is loaded without contacting the node, so its address is unused.
+        assert!(
+            Storage::load_or_initialize(&path, fvk, "unused".to_string(), 0, false)
+                .await
+                .is_ok()
+        );
+        assert!(
+            Storage::load_or_initialize(&path, fvk, "unused".to_string(), 0, true)
+                .await
+                .is_err()
+        );
     }
 }
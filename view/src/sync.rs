@@ -1,8 +1,11 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use penumbra_chain::{AnnotatedNotePayload, CompactBlock, Epoch, NoteSource};
-use penumbra_crypto::{FullViewingKey, IdentityKey, Note, NotePayload, Nullifier};
+use penumbra_crypto::{
+    asset, note, FullViewingKey, IdentityKey, Note, NotePayload, Nullifier, Value,
+};
 use penumbra_tct as tct;
+use penumbra_transaction::Transaction;
 
 use crate::{NoteRecord, QuarantinedNoteRecord, Storage};
 
@@ -25,6 +28,30 @@ impl FilteredBlock {
             .chain(self.spent_nullifiers.iter())
     }
 
+    /// The IDs of those `transactions` in this block which spend our notes and whose outputs
+    /// are all ours, i.e., which only pay ourselves.
+    pub fn self_send_transaction_ids(&self, transactions: &[Transaction]) -> BTreeSet<[u8; 32]> {
+        let spent_nullifiers = self.all_nullifiers().collect::<BTreeSet<_>>();
+
+        let mut our_outputs = BTreeMap::<[u8; 32], usize>::new();
+        for record in &self.new_notes {
+            if let NoteSource::Transaction { id } = record.source {
+                *our_outputs.entry(id).or_default() += 1;
+            }
+        }
+
+        transactions
+            .iter()
+            .filter(|tx| {
+                tx.spent_nullifiers()
+                    .any(|nf| spent_nullifiers.contains(&nf))
+            })
+            .map(|tx| (tx.id(), tx.note_payloads().count()))
+            .filter(|(id, outputs)| our_outputs.get(id) == Some(outputs))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
     pub fn inbound_transaction_ids(&self) -> BTreeSet<[u8; 32]> {
         let mut ids = BTreeSet::new();
         let sources = self.new_notes.iter().map(|n| n.source);
@@ -38,6 +65,195 @@ impl FilteredBlock {
     }
 }
 
+/// A summary of the changes recorded for a scanned block, so that a client can update its view of
+/// the wallet without re-reading all of its state.
+#[derive(Debug, Clone, Default)]
+pub struct BlockScanResult {
+    pub height: u64,
+    /// The notes we received in the block.
+    pub new_notes: Vec<NoteRecord>,
+    /// The commitments and values of our notes spent in the block.
+    pub spent_notes: Vec<(note::Commitment, Value)>,
+}
+
+impl BlockScanResult {
+    /// The net change in our balance of each asset caused by the block.
+    pub fn balance_delta(&self) -> BTreeMap<asset::Id, i128> {
+        let mut delta = BTreeMap::<asset::Id, i128>::new();
+        for record in &self.new_notes {
+            *delta.entry(record.note.asset_id()).or_default() += record.note.amount() as i128;
+        }
+        for (_, value) in &self.spent_notes {
+            *delta.entry(value.asset_id).or_default() -= value.amount as i128;
+        }
+        delta
+    }
+}
+
+/// Tracks our running balance across scanned blocks, reporting it every `interval` blocks, so
+/// that a client can show balances updating during a long sync.
+pub struct BalanceProgress<F> {
+    balance: BTreeMap<asset::Id, u64>,
+    interval: u64,
+    blocks_since_report: u64,
+    on_progress: F,
+}
+
+impl<F: FnMut(u64, &BTreeMap<asset::Id, u64>)> BalanceProgress<F> {
+    /// Start tracking from the balance as of the last synced block, calling `on_progress` with
+    /// the height and running balance after every `interval` blocks.
+    pub fn new(balance: BTreeMap<asset::Id, u64>, interval: u64, on_progress: F) -> Self {
+        Self {
+            balance,
+            interval: interval.max(1),
+            blocks_since_report: 0,
+            on_progress,
+        }
+    }
+
+    /// Record the changes in the next scanned block.
+    ///
+    /// If the block spends more of an asset than the running balance holds, the running balance
+    /// has diverged from our notes, so this returns an error and leaves the balance unchanged.
+    pub fn record_block(&mut self, result: &BlockScanResult) -> anyhow::Result<()> {
+        let mut balance = self.balance.clone();
+        for (asset_id, delta) in result.balance_delta() {
+            let amount = balance.get(&asset_id).copied().unwrap_or(0) as i128 + delta;
+            if amount < 0 {
+                return Err(anyhow::anyhow!(
+                    "block at height {} spends {} more of asset {} than the running balance",
+                    result.height,
+                    -amount,
+                    asset_id
+                ));
+            }
+            if amount == 0 {
+                balance.remove(&asset_id);
+            } else {
+                balance.insert(asset_id, amount as u64);
+            }
+        }
+        self.balance = balance;
+        self.advance(result.height);
+        Ok(())
+    }
+
+    /// Record that the next block, at `height`, didn't need scanning.
+    pub fn record_empty_block(&mut self, height: u64) {
+        self.advance(height);
+    }
+
+    /// The running balance.
+    pub fn balance(&self) -> &BTreeMap<asset::Id, u64> {
+        &self.balance
+    }
+
+    fn advance(&mut self, height: u64) {
+        self.blocks_since_report += 1;
+        if self.blocks_since_report == self.interval {
+            (self.on_progress)(height, &self.balance);
+            self.blocks_since_report = 0;
+        }
+    }
+}
+
+/// Statistics about the scanning of a single block, for diagnosing sync performance.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// The number of note payloads trial-decrypted, including quarantined ones.
+    pub note_payloads: usize,
+    /// The number of those note payloads which decrypted to one of our notes.
+    pub decrypted_notes: usize,
+    /// The number of our notes spent in the block.
+    pub spent_nullifiers: usize,
+}
+
+/// Scan and record a contiguous batch of blocks, in order.
+///
+/// The heights of the whole batch are checked up front, so that a gap is reported before any
+/// block is applied to the note commitment tree. Each block is recorded in `storage` as soon as it
+/// is scanned, so the stored tree and sync height always agree and a note found in one block of the
+/// batch is recognized when a later one spends it. Transactions aren't fetched, so only the notes
+/// and nullifiers of each block are recorded.
+pub async fn scan_blocks(
+    fvk: &FullViewingKey,
+    note_commitment_tree: &mut tct::Tree,
+    blocks: impl IntoIterator<Item = CompactBlock>,
+    epoch_duration: u64,
+    storage: &Storage,
+) -> anyhow::Result<()> {
+    let blocks = blocks.into_iter().collect::<Vec<_>>();
+
+    let next_height = storage
+        .last_sync_height()
+        .await?
+        .map(|height| height + 1)
+        .unwrap_or(0);
+    check_contiguous(next_height, blocks.iter().map(|block| block.height))?;
+
+    for block in blocks {
+        let filtered_block =
+            scan_block(fvk, note_commitment_tree, block, epoch_duration, storage).await?;
+        let height = filtered_block.height;
+        storage
+            .record_block(filtered_block, Vec::new(), note_commitment_tree)
+            .await?;
+        storage.record_anchor(height, note_commitment_tree.root());
+    }
+
+    Ok(())
+}
+
+/// The error returned when scanning the block at `height` into a note commitment tree with no
+/// room left for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TreeFull {
+    pub height: u64,
+}
+
+impl std::fmt::Display for TreeFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "note commitment tree is full, so block {} can't be scanned",
+            self.height
+        )
+    }
+}
+
+impl std::error::Error for TreeFull {}
+
+/// Check that the block at `height`, if `commitments` of its note commitments are inserted
+/// individually (or none, if its root is inserted), fits in `tree`.
+fn check_capacity(tree: &tct::Tree, height: u64, commitments: usize) -> Result<(), TreeFull> {
+    let position = tree.position().ok_or(TreeFull { height })?;
+    let remaining = (1usize << 16) - usize::from(position.commitment());
+    if commitments > remaining {
+        Err(TreeFull { height })
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that `heights` count up one at a time from `next_height`.
+fn check_contiguous(
+    next_height: u64,
+    heights: impl IntoIterator<Item = u64>,
+) -> anyhow::Result<()> {
+    let mut expected = next_height;
+    for height in heights {
+        if height != expected {
+            return Err(anyhow::anyhow!(
+                "expected block at height {} but got block at height {}",
+                expected,
+                height
+            ));
+        }
+        expected += 1;
+    }
+    Ok(())
+}
+
 #[tracing::instrument(skip(fvk, note_commitment_tree, note_payloads, nullifiers, storage))]
 pub async fn scan_block(
     fvk: &FullViewingKey,
@@ -66,6 +282,9 @@ pub async fn scan_block(
     let new_notes: Vec<NoteRecord>;
     let mut new_quarantined_notes: Vec<QuarantinedNoteRecord> = Vec::new();
 
+    // The number of note payloads we trial-decrypt in this block
+    let mut note_payload_count = note_payloads.len();
+
     // Nullifiers we've found in this block
     let spent_nullifiers: Vec<Nullifier> = nullifiers;
     let mut spent_quarantined_nullifiers: BTreeMap<IdentityKey, Vec<Nullifier>> = BTreeMap::new();
@@ -87,6 +306,7 @@ pub async fn scan_block(
                 .or_default()
                 .extend(unbonding.nullifiers);
             // Trial-decrypt the quarantined notes, keeping track of the ones that were meant for us
+            note_payload_count += unbonding.note_payloads.len();
             let decryptions = unbonding
                 .note_payloads
                 .into_iter()
@@ -120,6 +340,14 @@ pub async fn scan_block(
         }
     }
 
+    // The note commitment tree has a fixed capacity, so if it fills up, we report an error rather
+    // than panicking, so the caller knows that scanning cannot continue with this tree. Check that
+    // the whole block fits first, so that the tree isn't left holding part of it.
+    let commitments = match decrypted_applied_notes.is_empty() {
+        true => 0,
+        false => note_payloads.len(),
+    };
+    check_capacity(note_commitment_tree, height, commitments)?;
     if decrypted_applied_notes.is_empty() {
         // We didn't find any notes for us in this block
         new_notes = Vec::new();
@@ -128,52 +356,47 @@ pub async fn scan_block(
         // tree instead of processing each commitment individually
         note_commitment_tree
             .insert_block(block_root)
-            .expect("inserting a block root must succeed");
+            .map_err(|_| TreeFull { height })?;
     } else {
         // If we found at least one note for us in this block, we have to explicitly construct the
         // whole block in the NCT by inserting each commitment one at a time
-        new_notes = note_payloads
-            .into_iter()
-            .filter_map(|AnnotatedNotePayload { payload, source }| {
-                let note_commitment = payload.note_commitment;
+        let mut notes = Vec::new();
+        for AnnotatedNotePayload { payload, source } in note_payloads {
+            let note_commitment = payload.note_commitment;
 
-                if let Some(note) = decrypted_applied_notes.remove(&note_commitment) {
-                    // Keep track of this commitment for later witnessing
-                    let position = note_commitment_tree
-                        .insert(tct::Witness::Keep, note_commitment)
-                        .expect("inserting a commitment must succeed");
+            if let Some(note) = decrypted_applied_notes.remove(&note_commitment) {
+                // Keep track of this commitment for later witnessing
+                let position = note_commitment_tree
+                    .insert(tct::Witness::Keep, note_commitment)
+                    .map_err(|_| TreeFull { height })?;
 
-                    let nullifier = fvk.derive_nullifier(position, &note_commitment);
+                let nullifier = fvk.derive_nullifier(position, &note_commitment);
 
-                    let diversifier = &note.diversifier();
+                let diversifier = &note.diversifier();
 
-                    let record = NoteRecord {
-                        note_commitment,
-                        height_spent: None,
-                        height_created: height,
-                        note,
-                        address_index: fvk.incoming().index_for_diversifier(diversifier),
-                        nullifier,
-                        position,
-                        source,
-                    };
-
-                    Some(record)
-                } else {
-                    // Don't remember this commitment; it wasn't ours
-                    note_commitment_tree
-                        .insert(tct::Witness::Forget, note_commitment)
-                        .expect("inserting a commitment must succeed");
-
-                    None
-                }
-            })
-            .collect();
+                notes.push(NoteRecord {
+                    note_commitment,
+                    height_spent: None,
+                    height_created: height,
+                    note,
+                    address_index: fvk.incoming().index_for_diversifier(diversifier),
+                    nullifier,
+                    position,
+                    source,
+                });
+            } else {
+                // Don't remember this commitment; it wasn't ours
+                note_commitment_tree
+                    .insert(tct::Witness::Forget, note_commitment)
+                    .map_err(|_| TreeFull { height })?;
+            }
+        }
+        new_notes = notes;
 
         // End the block in the commitment tree
         note_commitment_tree
             .end_block()
-            .expect("ending the block must succed");
+            .map_err(|_| TreeFull { height })?;
     }
 
     // If we've also reached the end of the epoch, end the epoch in the commitment tree
@@ -181,7 +404,7 @@ pub async fn scan_block(
         tracing::debug!(?height, "end of epoch");
         note_commitment_tree
             .end_epoch()
-            .expect("ending the epoch must succeed");
+            .map_err(|_| TreeFull { height })?;
     }
 
     // Print the TCT root for debugging
@@ -212,5 +435,365 @@ pub async fn scan_block(
         tracing::debug!(?result, "scan result contained quarantined things");
     }
 
+    storage.record_scan_stats(
+        height,
+        ScanStats {
+            note_payloads: note_payload_count,
+            decrypted_notes: result.new_notes.len() + result.new_quarantined_notes.len(),
+            spent_nullifiers: result.all_nullifiers().count(),
+        },
+    );
+
     Ok(result)
 }
+
+/// Scan a block which the server has pre-filtered to contain only the nullifiers of our notes.
+///
+/// Since every nullifier in such a block should spend one of our notes, a nullifier which doesn't,
+/// and doesn't belong to a note we pruned, is an error, indicating that the server's filter and
+/// our state disagree. The nullifiers are checked before the block is scanned, so the note
+/// commitment tree is left untouched on error.
+pub async fn scan_filtered_block(
+    fvk: &FullViewingKey,
+    note_commitment_tree: &mut tct::Tree,
+    block: CompactBlock,
+    epoch_duration: u64,
+    storage: &Storage,
+) -> anyhow::Result<FilteredBlock> {
+    let ours = storage.filter_nullifiers(block.nullifiers.clone()).await?;
+    let mut unknown = 0;
+    for nullifier in &block.nullifiers {
+        if !ours.contains(nullifier) && !storage.is_retired_nullifier(nullifier).await? {
+            unknown += 1;
+        }
+    }
+
+    if unknown > 0 {
+        return Err(anyhow::anyhow!(
+            "filtered block at height {} contained {} nullifiers not spending any of our notes",
+            block.height,
+            unknown
+        ));
+    }
+
+    scan_block(fvk, note_commitment_tree, block, epoch_duration, storage).await
+}
+
+/// The IDs of the assets of any new notes in the scanned `blocks` which aren't among the `known`
+/// assets, each listed once, so that their denominations can be fetched in a single query.
+pub fn unknown_asset_ids<'a>(
+    blocks: impl IntoIterator<Item = &'a FilteredBlock>,
+    known: &BTreeSet<asset::Id>,
+) -> Vec<asset::Id> {
+    let mut seen = BTreeSet::new();
+    blocks
+        .into_iter()
+        .flat_map(|block| {
+            block
+                .new_notes
+                .iter()
+                .map(|record| record.note.asset_id())
+                .chain(
+                    block
+                        .new_quarantined_notes
+                        .iter()
+                        .map(|record| record.note.asset_id()),
+                )
+        })
+        .filter(|asset_id| !known.contains(asset_id) && seen.insert(*asset_id))
+        .collect()
+}
+
+/// The heights among `relevant` which are yet to be scanned after the last synced height, each
+/// listed once and in order, e.g. when a detection service has told us which blocks contain
+/// activity relevant to us.
+///
+/// Only these blocks need to be fetched in full, but the note commitment tree must still be
+/// advanced past every skipped block, by inserting its note commitments and ending the block,
+/// so that positions and anchors stay in step with the chain's tree.
+pub fn missing_relevant_heights(
+    last_sync_height: Option<u64>,
+    relevant: impl IntoIterator<Item = u64>,
+) -> Vec<u64> {
+    relevant
+        .into_iter()
+        .filter(|height| last_sync_height.map_or(true, |synced| *height > synced))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        STAKING_TOKEN_ASSET_ID,
+    };
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::test_utils::{note_payload, note_record, temp_storage};
+
+    #[test]
+    fn balance_delta_nets_receives_and_spends() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+
+        let received = note_record(fvk, 0, 30, *STAKING_TOKEN_ASSET_ID, 1, 1);
+        let spent = note_record(fvk, 0, 100, *STAKING_TOKEN_ASSET_ID, 0, 0);
+
+        let result = BlockScanResult {
+            height: 1,
+            new_notes: vec![received],
+            spent_notes: vec![(spent.note_commitment, spent.note.value())],
+        };
+
+        assert_eq!(
+            result.balance_delta().get(&*STAKING_TOKEN_ASSET_ID),
+            Some(&-70)
+        );
+    }
+
+    #[test]
+    fn balance_progress_reports_at_interval() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+
+        let mut reports = Vec::new();
+        let mut progress = BalanceProgress::new(BTreeMap::new(), 3, |height, balance| {
+            reports.push((height, balance.get(&*STAKING_TOKEN_ASSET_ID).copied()))
+        });
+
+        // Receive 10 in every other block.
+        for height in 1..=7u64 {
+            if height % 2 == 1 {
+                progress
+                    .record_block(&BlockScanResult {
+                        height,
+                        new_notes: vec![note_record(
+                            fvk,
+                            0,
+                            10,
+                            *STAKING_TOKEN_ASSET_ID,
+                            height,
+                            height,
+                        )],
+                        spent_notes: Vec::new(),
+                    })
+                    .unwrap();
+            } else {
+                progress.record_empty_block(height);
+            }
+        }
+
+        assert_eq!(progress.balance().get(&*STAKING_TOKEN_ASSET_ID), Some(&40));
+        drop(progress);
+        assert_eq!(reports, vec![(3, Some(20)), (6, Some(30))]);
+    }
+
+    #[test]
+    fn balance_progress_rejects_underflow() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let record = note_record(fvk, 0, 10, *STAKING_TOKEN_ASSET_ID, 1, 0);
+        let initial = [(*STAKING_TOKEN_ASSET_ID, 5)].into_iter().collect();
+
+        let mut progress = BalanceProgress::new(initial, 1, |_, _| {});
+        let overspend = BlockScanResult {
+            height: 1,
+            new_notes: Vec::new(),
+            spent_notes: vec![(record.note_commitment, record.note.value())],
+        };
+        assert!(progress.record_block(&overspend).is_err());
+        assert_eq!(progress.balance().get(&*STAKING_TOKEN_ASSET_ID), Some(&5));
+    }
+
+    #[test]
+    fn unknown_asset_ids_are_listed_once() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+
+        let first_id = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+        let second_id = asset::REGISTRY.parse_denom("ugn").unwrap().id();
+
+        let block = |height: u64, asset_ids: &[asset::Id]| FilteredBlock {
+            new_notes: asset_ids
+                .iter()
+                .map(|&asset_id| note_record(fvk, 0, 1, asset_id, height, height))
+                .collect(),
+            new_quarantined_notes: Vec::new(),
+            spent_nullifiers: Vec::new(),
+            spent_quarantined_nullifiers: BTreeMap::new(),
+            slashed_validators: Vec::new(),
+            height,
+        };
+
+        let blocks = [
+            block(1, &[first_id, *STAKING_TOKEN_ASSET_ID]),
+            block(2, &[second_id, first_id]),
+        ];
+        let known = [*STAKING_TOKEN_ASSET_ID].into_iter().collect();
+
+        assert_eq!(
+            unknown_asset_ids(&blocks, &known),
+            vec![first_id, second_id]
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_blocks_records_each_block() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (address, _dtk) = fvk.incoming().payment_address(0u64.into());
+        let other_sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let (other_address, _dtk) = other_sk
+            .full_viewing_key()
+            .incoming()
+            .payment_address(0u64.into());
+        let (_dir, storage) = temp_storage(fvk).await;
+        let epoch_duration = 4;
+        let value = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+
+        // Every block pays someone else, and every third block also pays us. The note we receive
+        // in the first block is spent in the sixth, in the same batch.
+        let mut chain_nct = tct::Tree::new();
+        let mut received = Vec::new();
+        let mut blocks = Vec::new();
+        for height in 0..10u64 {
+            let mut notes = vec![(Note::generate(&mut OsRng, &other_address, value(1)), false)];
+            if height % 3 == 0 {
+                notes.push((
+                    Note::generate(&mut OsRng, &address, value(height + 1)),
+                    true,
+                ));
+            }
+
+            let mut block = CompactBlock {
+                height,
+                ..Default::default()
+            };
+            for (note, ours) in notes {
+                let position = chain_nct.insert(tct::Witness::Keep, note.commit()).unwrap();
+                if ours {
+                    received.push(fvk.derive_nullifier(position, &note.commit()));
+                }
+                block.note_payloads.push(note_payload(&note));
+            }
+            if height == 5 {
+                block.nullifiers.push(received[0]);
+            }
+            block.block_root = chain_nct.end_block().unwrap();
+            if Epoch::from_height(height, epoch_duration).is_epoch_end(height) {
+                block.epoch_root = Some(chain_nct.end_epoch().unwrap());
+            }
+            blocks.push(block);
+        }
+
+        let mut nct = tct::Tree::new();
+        scan_blocks(fvk, &mut nct, blocks, epoch_duration, &storage)
+            .await
+            .unwrap();
+
+        assert_eq!(storage.last_sync_height().await.unwrap(), Some(9));
+        assert_eq!(nct.root(), chain_nct.root());
+        assert_eq!(
+            storage.recent_anchors().last(),
+            Some(&(9, chain_nct.root()))
+        );
+
+        let notes = storage.notes(true, None, None, 0, true).await.unwrap();
+        assert_eq!(notes.len(), 4);
+        let spent = notes
+            .iter()
+            .filter(|record| record.height_spent.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(spent.len(), 1);
+        assert_eq!(spent[0].nullifier, received[0]);
+        assert_eq!(spent[0].height_spent, Some(5));
+    }
+
+    #[tokio::test]
+    async fn filtered_block_with_unknown_nullifier_leaves_tree_untouched() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+
+        // The block pays us a note, but its nullifier doesn't spend any note we know of.
+        let mut nct = tct::Tree::new();
+        let (root, position) = (nct.root(), nct.position());
+        let unrecorded = note_record(fvk, 0, 1, *STAKING_TOKEN_ASSET_ID, 0, 0);
+        let block = CompactBlock {
+            height: 0,
+            note_payloads: vec![note_payload(&unrecorded.note)],
+            nullifiers: vec![unrecorded.nullifier],
+            ..Default::default()
+        };
+
+        assert!(scan_filtered_block(fvk, &mut nct, block, 10, &storage)
+            .await
+            .is_err());
+        assert_eq!(nct.root(), root);
+        assert_eq!(nct.position(), position);
+    }
+
+    #[tokio::test]
+    async fn full_tree_is_reported_as_error() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (_dir, storage) = temp_storage(fvk).await;
+
+        // Fill the tree with empty epochs, so there's no room left for another block.
+        let mut nct = tct::Tree::new();
+        while nct.position().is_some() {
+            nct.end_epoch().unwrap();
+        }
+
+        // Whether or not the block pays us, scanning it fails rather than panicking.
+        let ours = note_record(fvk, 0, 1, *STAKING_TOKEN_ASSET_ID, 0, 0);
+        for note_payloads in [Vec::new(), vec![note_payload(&ours.note)]] {
+            let block = CompactBlock {
+                height: 0,
+                note_payloads,
+                ..Default::default()
+            };
+            let error = scan_block(fvk, &mut nct, block, 10, &storage)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<TreeFull>(),
+                Some(&TreeFull { height: 0 })
+            );
+        }
+    }
+
+    #[test]
+    fn blocks_too_large_for_the_tree_are_rejected_up_front() {
+        let nct = tct::Tree::new();
+        assert_eq!(check_capacity(&nct, 3, 1 << 16), Ok(()));
+        assert_eq!(
+            check_capacity(&nct, 3, (1 << 16) + 1),
+            Err(TreeFull { height: 3 })
+        );
+    }
+
+    #[test]
+    fn batch_heights_must_be_contiguous() {
+        assert!(check_contiguous(5, 5..15).is_ok());
+        assert!(check_contiguous(5, Vec::new()).is_ok());
+        assert!(check_contiguous(5, 6..16).is_err());
+        assert!(check_contiguous(5, [5, 6, 8, 9]).is_err());
+    }
+
+    #[test]
+    fn missing_relevant_heights_skips_synced_blocks() {
+        assert_eq!(
+            missing_relevant_heights(Some(10), [3, 15, 10, 11, 15, 40]),
+            [11, 15, 40]
+        );
+        assert_eq!(missing_relevant_heights(None, [2, 0]), [0, 2]);
+        assert!(missing_relevant_heights(Some(50), [3, 15, 40]).is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+//! Fixtures shared by the tests of this crate.
+
+use std::collections::BTreeMap;
+
+use camino::Utf8PathBuf;
+use penumbra_chain::{params::ChainParams, AnnotatedNotePayload, NoteSource};
+use penumbra_crypto::{
+    asset, ka, keys::AddressIndex, Fr, FullViewingKey, Note, NotePayload, Nullifier, One, Value,
+};
+use penumbra_tct as tct;
+use rand_core::OsRng;
+use tempfile::TempDir;
+
+use crate::{FilteredBlock, NoteRecord, Storage};
+
+/// A record of a freshly generated note of `amount` of `asset_id` to the address of `fvk` with
+/// index `address_index`, created at `height` and placed at `position` in the note commitment
+/// tree.
+pub fn note_record(
+    fvk: &FullViewingKey,
+    address_index: u64,
+    amount: u64,
+    asset_id: asset::Id,
+    height: u64,
+    position: u64,
+) -> NoteRecord {
+    let address_index = AddressIndex::from(address_index);
+    let (address, _dtk) = fvk.incoming().payment_address(address_index);
+    let note = Note::generate(&mut OsRng, &address, Value { amount, asset_id });
+    let position = position.into();
+    NoteRecord {
+        note_commitment: note.commit(),
+        nullifier: fvk.derive_nullifier(position, &note.commit()),
+        address_index,
+        height_created: height,
+        height_spent: None,
+        position,
+        source: NoteSource::Genesis,
+        note,
+    }
+}
+
+/// The payload of `note`, as it appears in a compact block.
+pub fn note_payload(note: &Note) -> AnnotatedNotePayload {
+    let esk = ka::Secret::new_from_field(Fr::one());
+    AnnotatedNotePayload {
+        payload: NotePayload {
+            note_commitment: note.commit(),
+            ephemeral_key: esk.diversified_public(&note.diversified_generator()),
+            encrypted_note: note.encrypt(&esk),
+        },
+        source: NoteSource::Genesis,
+    }
+}
+
+/// Fresh storage for `fvk` in a temporary directory, which is deleted when the returned
+/// [`TempDir`] is dropped.
+pub async fn temp_storage(fvk: &FullViewingKey) -> (TempDir, Storage) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = Utf8PathBuf::from_path_buf(dir.path().join("storage.sqlite")).unwrap();
+    let storage = Storage::initialize(path, fvk.clone(), ChainParams::default())
+        .await
+        .unwrap();
+    (dir, storage)
+}
+
+/// The scan result of a block at `height` which pays us `new_notes` and spends the notes with the
+/// given `spent_nullifiers`.
+pub fn filtered_block(
+    height: u64,
+    new_notes: Vec<NoteRecord>,
+    spent_nullifiers: Vec<Nullifier>,
+) -> FilteredBlock {
+    FilteredBlock {
+        new_notes,
+        new_quarantined_notes: Vec::new(),
+        spent_nullifiers,
+        spent_quarantined_nullifiers: BTreeMap::new(),
+        slashed_validators: Vec::new(),
+        height,
+    }
+}
+
+/// Record the block at `height` paying us `notes`, witnessing them in `nct`.
+pub async fn record_notes(
+    storage: &Storage,
+    nct: &mut tct::Tree,
+    height: u64,
+    notes: Vec<NoteRecord>,
+) {
+    for record in &notes {
+        nct.insert(tct::Witness::Keep, record.note_commitment)
+            .unwrap();
+    }
+    storage
+        .record_block(filtered_block(height, notes, Vec::new()), Vec::new(), nct)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+
+use penumbra_crypto::Value;
+
+/// A transaction this wallet took part in, with the values it sent to us and spent from us.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransactionRecord {
+    /// The height of the block containing the transaction.
+    pub height: u64,
+    pub tx_hash: [u8; 32],
+    pub tx_bytes: Vec<u8>,
+    /// The values of the notes we received in the transaction.
+    pub received: Vec<Value>,
+    /// The values of our notes spent by the transaction.
+    pub spent: Vec<Value>,
+}
+
+/// Assemble the records of the given transactions from the notes they created for us and the notes
+/// of ours they spent, each given with the hash of its transaction and the height it was created
+/// or spent at, sorted by height.
+///
+/// Transactions with neither received nor spent notes have no known height, and are omitted.
+pub(crate) fn transaction_history(
+    transactions: impl IntoIterator<Item = ([u8; 32], Vec<u8>)>,
+    received: impl IntoIterator<Item = ([u8; 32], u64, Value)>,
+    spent: impl IntoIterator<Item = ([u8; 32], u64, Value)>,
+) -> Vec<TransactionRecord> {
+    let mut records = transactions
+        .into_iter()
+        .map(|(tx_hash, tx_bytes)| {
+            (
+                tx_hash,
+                (
+                    None,
+                    TransactionRecord {
+                        height: 0,
+                        tx_hash,
+                        tx_bytes,
+                        received: Vec::new(),
+                        spent: Vec::new(),
+                    },
+                ),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    for (tx_hash, height, value) in received {
+        if let Some((record_height, record)) = records.get_mut(&tx_hash) {
+            *record_height = Some(height);
+            record.received.push(value);
+        }
+    }
+    for (tx_hash, height, value) in spent {
+        if let Some((record_height, record)) = records.get_mut(&tx_hash) {
+            *record_height = Some(height);
+            record.spent.push(value);
+        }
+    }
+
+    let mut history = records
+        .into_values()
+        .filter_map(|(height, record)| {
+            Some(TransactionRecord {
+                height: height?,
+                ..record
+            })
+        })
+        .collect::<Vec<_>>();
+    history.sort_by_key(|record| record.height);
+    history
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::STAKING_TOKEN_ASSET_ID;
+
+    use super::*;
+
+    #[test]
+    fn sends_and_receives_are_categorized() {
+        let value = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let receive = [1u8; 32];
+        let send = [2u8; 32];
+        let unrelated = [3u8; 32];
+
+        let history = transaction_history(
+            [
+                (send, b"send".to_vec()),
+                (receive, b"receive".to_vec()),
+                (unrelated, b"unrelated".to_vec()),
+            ],
+            [(receive, 5, value(100)), (send, 9, value(60))],
+            [(send, 9, value(100))],
+        );
+
+        assert_eq!(
+            history,
+            vec![
+                TransactionRecord {
+                    height: 5,
+                    tx_hash: receive,
+                    tx_bytes: b"receive".to_vec(),
+                    received: vec![value(100)],
+                    spent: Vec::new(),
+                },
+                TransactionRecord {
+                    height: 9,
+                    tx_hash: send,
+                    tx_bytes: b"send".to_vec(),
+                    received: vec![value(60)],
+                    spent: vec![value(100)],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,10 @@
+/// The state of a transaction submitted by this wallet, as seen by scanning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionState {
+    /// The transaction has not been seen in a block yet.
+    Pending,
+    /// The transaction was included in a block.
+    Confirmed,
+    /// The transaction was not included before its expiry height.
+    Failed,
+}
@@ -1,8 +1,9 @@
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     sync::{Arc, Mutex},
 };
 
+use anyhow::Context;
 use penumbra_chain::{sync::CompactBlock, Epoch};
 use penumbra_crypto::{Asset, FullViewingKey, Nullifier};
 use penumbra_proto::{
@@ -21,10 +22,13 @@ use tonic::transport::Channel;
 use penumbra_proto::client::specific::specific_query_client::SpecificQueryClient;
 
 use crate::{
-    sync::{scan_block, FilteredBlock},
+    sync::{scan_block, unknown_asset_ids, BalanceProgress, FilteredBlock},
     Storage,
 };
 
+/// The number of blocks between reports of the running balance during sync.
+const BALANCE_PROGRESS_INTERVAL: u64 = 1000;
+
 pub struct Worker {
     storage: Storage,
     client: ObliviousQueryClient<Channel>,
@@ -204,6 +208,19 @@ impl Worker {
 
         let epoch_duration = self.storage.chain_params().await?.epoch_duration;
 
+        // Report our balance as it changes, so a long sync shows progress. Hidden assets are
+        // tracked, but left out of the report.
+        let balance = self.storage.unspent_totals().await?;
+        let hidden = self.storage.hidden_assets().await?;
+        let mut progress =
+            BalanceProgress::new(balance, BALANCE_PROGRESS_INTERVAL, |height, balance| {
+                let balance = balance
+                    .iter()
+                    .filter(|(asset_id, _)| !hidden.contains(asset_id))
+                    .collect::<BTreeMap<_, _>>();
+                tracing::info!(height, ?balance, "sync progress")
+            });
+
         let mut stream = self
             .client
             .compact_block_range(tonic::Request::new(CompactBlockRangeRequest {
@@ -240,15 +257,20 @@ impl Worker {
             if !block.requires_scanning() {
                 // Optimization: if the block is empty, seal the in-memory NCT,
                 // and skip touching the database:
-                nct_guard.end_block().unwrap();
+                nct_guard
+                    .end_block()
+                    .context("note commitment tree is full")?;
                 // We also need to end the epoch, since if there are no funding streams, then an
                 // epoch boundary won't necessarily require scanning:
                 if Epoch::from_height(height, epoch_duration).is_epoch_end(height) {
                     nct_guard
                         .end_epoch()
-                        .expect("ending the epoch must succeed");
+                        .context("note commitment tree is full")?;
                 }
                 self.storage.record_empty_block(height).await?;
+                progress.record_empty_block(height);
+                // Commit the empty blocks every so often, so a long sync keeps its progress.
+                self.storage.checkpoint_if_due(&mut nct_guard).await?;
                 // Notify all watchers of the new height we just recorded.
                 self.sync_height_tx.send(height)?;
             } else {
@@ -265,12 +287,40 @@ impl Worker {
                 // Download any transactions we detected.
                 let transactions = self.fetch_transactions(&filtered_block).await?;
 
-                self.storage
+                let result = self
+                    .storage
                     .record_block(filtered_block.clone(), transactions, &mut nct_guard)
                     .await?;
+                // The recorded notes remain authoritative, so a divergent running balance only
+                // affects progress reports.
+                if let Err(e) = progress.record_block(&result) {
+                    tracing::warn!(?e, "running balance diverged from recorded notes");
+                }
+
+                // If we received notes of assets we don't know the denominations of, refresh
+                // the asset list, which covers all of them in a single query.
+                if !filtered_block.new_notes.is_empty()
+                    || !filtered_block.new_quarantined_notes.is_empty()
+                {
+                    let known_assets = self
+                        .storage
+                        .assets()
+                        .await?
+                        .into_iter()
+                        .map(|asset| asset.id)
+                        .collect::<BTreeSet<_>>();
+                    let unknown_assets = unknown_asset_ids([&filtered_block], &known_assets);
+                    if !unknown_assets.is_empty() {
+                        tracing::info!(?unknown_assets, "received notes of unknown assets");
+                        self.fetch_assets().await?;
+                    }
+                }
                 // Notify all watchers of the new height we just recorded.
                 self.sync_height_tx.send(filtered_block.height)?;
             }
+            // Remember the anchor reached at this height.
+            self.storage.record_anchor(height, nct_guard.root());
+
             #[cfg(feature = "nct-divergence-check")]
             nct_divergence_check(&mut self.specific_client, height, nct_guard.root()).await?;
 
@@ -0,0 +1,51 @@
+//! Choosing the fee paid by a planned transaction.
+
+/// The cost of a transaction, as a base cost plus a cost per spend and per output, from which
+/// [`FeeChoice::Auto`] estimates a fee scaling with the size of the transaction.
+///
+/// The default parameters are all zero, matching the zero fee used when none is requested.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeParameters {
+    pub base: u64,
+    pub per_spend: u64,
+    pub per_output: u64,
+}
+
+impl FeeParameters {
+    /// The fee for a transaction with the given numbers of spends and outputs.
+    pub fn estimate_fee(&self, num_spends: usize, num_outputs: usize) -> u64 {
+        self.base + self.per_spend * num_spends as u64 + self.per_output * num_outputs as u64
+    }
+}
+
+/// The fee to pay for a planned transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeChoice {
+    /// Pay exactly this fee.
+    Exact(u64),
+    /// Pay the fee estimated from the planned transaction's size.
+    Auto(FeeParameters),
+}
+
+impl From<u64> for FeeChoice {
+    fn from(fee: u64) -> Self {
+        FeeChoice::Exact(fee)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimated_fee_scales_with_spends() {
+        let parameters = FeeParameters {
+            base: 10,
+            per_spend: 5,
+            per_output: 2,
+        };
+        assert_eq!(parameters.estimate_fee(1, 2), 19);
+        assert_eq!(parameters.estimate_fee(3, 2), 29);
+        assert_eq!(FeeParameters::default().estimate_fee(3, 2), 0);
+    }
+}
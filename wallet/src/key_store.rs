@@ -1,10 +1,66 @@
-use penumbra_crypto::keys::{SeedPhrase, SpendKey};
-use serde::{Deserialize, Serialize};
+use anyhow::anyhow;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use penumbra_crypto::{
+    keys::{AddressIndex, SeedPhrase, SpendKey},
+    memo::MemoPlaintext,
+    Address, Value,
+};
+use penumbra_transaction::plan::OutputPlan;
+use rand_core::{CryptoRng, RngCore};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+
+/// The number of address indices reserved for each account; see [`KeyStore::address_for_account`].
+pub const ADDRESSES_PER_ACCOUNT: u64 = 1 << 32;
+
+/// The bytes at the start of an encrypted wallet file, followed by a format version byte.
+const ENCRYPTED_MAGIC: &[u8; 4] = b"PKS\0";
+/// The current version of the encrypted wallet file format.
+///
+/// The version fixes the key derivation function and cipher, which are Argon2id and
+/// XChaCha20-Poly1305 in version 1; changing either requires a new version.
+const ENCRYPTED_VERSION: u8 = 1;
+/// The Argon2id memory cost, in KiB, used to derive the encryption key from a passphrase.
+const ENCRYPTED_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+/// The Argon2id number of passes used to derive the encryption key from a passphrase.
+const ENCRYPTED_ARGON2_ITERATIONS: u32 = 2;
+/// The Argon2id degree of parallelism used to derive the encryption key from a passphrase.
+const ENCRYPTED_ARGON2_PARALLELISM: u32 = 1;
+/// The largest Argon2id memory cost, in KiB, accepted when decrypting, so that a corrupted or
+/// malicious file can't make key derivation exhaust memory.
+const MAX_ARGON2_MEMORY_KIB: u32 = 1 << 20;
+/// The largest Argon2id number of passes accepted when decrypting.
+const MAX_ARGON2_ITERATIONS: u32 = 16;
+const SALT_LEN_BYTES: usize = 16;
+const NONCE_LEN_BYTES: usize = 24;
+const HEADER_LEN_BYTES: usize =
+    ENCRYPTED_MAGIC.len() + 1 + 3 * 4 + SALT_LEN_BYTES + NONCE_LEN_BYTES;
 
 /// A wallet file storing a single spend authority.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct KeyStore {
     pub spend_key: SpendKey,
+    /// Whether this is a throwaway wallet (e.g. for tests) which must never be written out;
+    /// serializing an ephemeral wallet fails. See [`KeyStore::into_ephemeral`].
+    #[serde(skip)]
+    pub ephemeral: bool,
+}
+
+impl Serialize for KeyStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.ephemeral {
+            return Err(serde::ser::Error::custom(
+                "refusing to serialize an ephemeral wallet",
+            ));
+        }
+
+        let mut state = serializer.serialize_struct("KeyStore", 1)?;
+        state.serialize_field("spend_key", &self.spend_key)?;
+        state.end()
+    }
 }
 
 impl KeyStore {
@@ -17,8 +73,8 @@ impl KeyStore {
         }
         use std::io::Write;
         let path = path.as_ref();
-        let mut file = std::fs::File::create(path)?;
         let data = serde_json::to_vec(self)?;
+        let mut file = std::fs::File::create(path)?;
         file.write_all(&data)?;
         Ok(())
     }
@@ -28,12 +84,286 @@ impl KeyStore {
         serde_json::from_slice(std::fs::read(path)?.as_slice()).map_err(Into::into)
     }
 
+    /// Encrypt the wallet data under a key derived from `passphrase`.
+    ///
+    /// The result starts with a versioned header holding the KDF parameters and nonce, followed
+    /// by the ciphertext of the JSON encoding written by [`KeyStore::save`]; both the header and
+    /// the ciphertext are authenticated.
+    pub fn encrypt<R: RngCore + CryptoRng>(
+        &self,
+        passphrase: &str,
+        mut rng: R,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut salt = [0u8; SALT_LEN_BYTES];
+        rng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LEN_BYTES];
+        rng.fill_bytes(&mut nonce);
+
+        let mut encrypted = Vec::with_capacity(HEADER_LEN_BYTES);
+        encrypted.extend_from_slice(ENCRYPTED_MAGIC);
+        encrypted.push(ENCRYPTED_VERSION);
+        for param in [
+            ENCRYPTED_ARGON2_MEMORY_KIB,
+            ENCRYPTED_ARGON2_ITERATIONS,
+            ENCRYPTED_ARGON2_PARALLELISM,
+        ] {
+            encrypted.extend_from_slice(&param.to_le_bytes());
+        }
+        encrypted.extend_from_slice(&salt);
+        encrypted.extend_from_slice(&nonce);
+
+        let params = Params::new(
+            ENCRYPTED_ARGON2_MEMORY_KIB,
+            ENCRYPTED_ARGON2_ITERATIONS,
+            ENCRYPTED_ARGON2_PARALLELISM,
+            Some(32),
+        )
+        .map_err(|e| anyhow!("invalid key derivation parameters: {}", e))?;
+        let cipher = passphrase_cipher(passphrase, &salt, params)?;
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: serde_json::to_vec(self)?.as_slice(),
+                    aad: &encrypted,
+                },
+            )
+            .map_err(|_| anyhow!("wallet encryption failed"))?;
+        encrypted.extend_from_slice(&ciphertext);
+
+        Ok(encrypted)
+    }
+
+    /// Decrypt wallet data produced by [`KeyStore::encrypt`].
+    ///
+    /// Fails if the data is not an encrypted wallet, was corrupted, or was encrypted under a
+    /// different passphrase.
+    pub fn decrypt(encrypted: &[u8], passphrase: &str) -> anyhow::Result<Self> {
+        if encrypted.len() < HEADER_LEN_BYTES || !encrypted.starts_with(ENCRYPTED_MAGIC) {
+            return Err(anyhow!("not an encrypted wallet file"));
+        }
+        let (aad, ciphertext) = encrypted.split_at(HEADER_LEN_BYTES);
+        let (version, header) = aad[ENCRYPTED_MAGIC.len()..]
+            .split_first()
+            .expect("header length was checked");
+        if *version != ENCRYPTED_VERSION {
+            return Err(anyhow!("unsupported encrypted wallet version {}", version));
+        }
+        let (params, header) = header.split_at(3 * 4);
+        let (salt, nonce) = header.split_at(SALT_LEN_BYTES);
+        let param = |i: usize| {
+            u32::from_le_bytes(
+                params[4 * i..4 * (i + 1)]
+                    .try_into()
+                    .expect("header length was checked"),
+            )
+        };
+        if param(0) > MAX_ARGON2_MEMORY_KIB || param(1) > MAX_ARGON2_ITERATIONS {
+            return Err(anyhow!("invalid key derivation parameters"));
+        }
+        let params = Params::new(param(0), param(1), param(2), Some(32))
+            .map_err(|e| anyhow!("invalid key derivation parameters: {}", e))?;
+
+        let plaintext = passphrase_cipher(passphrase, salt, params)?
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow!("wrong passphrase, or the wallet file is corrupted"))?;
+
+        serde_json::from_slice(&plaintext).map_err(Into::into)
+    }
+
+    /// Encrypt the wallet data under `passphrase` and write it to the provided path.
+    pub fn save_encrypted<R: RngCore + CryptoRng>(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        passphrase: &str,
+        rng: R,
+    ) -> anyhow::Result<()> {
+        if path.as_ref().exists() {
+            return Err(anyhow::anyhow!(
+                "Wallet file already exists, refusing to overwrite it"
+            ));
+        }
+        std::fs::write(path, self.encrypt(passphrase, rng)?)?;
+        Ok(())
+    }
+
+    /// Read and decrypt the wallet data from the provided path.
+    pub fn load_encrypted(
+        path: impl AsRef<std::path::Path>,
+        passphrase: &str,
+    ) -> anyhow::Result<Self> {
+        Self::decrypt(std::fs::read(path)?.as_slice(), passphrase)
+    }
+
     /// Create a new wallet.
     pub fn from_seed_phrase(seed_phrase: SeedPhrase) -> Self {
         // Currently we support a single spend authority per wallet. In the future,
         // we can derive multiple spend seeds from a single seed phrase.
         let spend_key = SpendKey::from_seed_phrase(seed_phrase, 0);
 
-        Self { spend_key }
+        Self {
+            spend_key,
+            ephemeral: false,
+        }
+    }
+
+    /// Mark this wallet as ephemeral, so that any attempt to save, encrypt or otherwise serialize
+    /// it fails rather than writing its key material anywhere.
+    pub fn into_ephemeral(self) -> Self {
+        Self {
+            ephemeral: true,
+            ..self
+        }
+    }
+
+    /// The address index and address of the given account.
+    ///
+    /// Each account owns a fixed range of [`ADDRESSES_PER_ACCOUNT`] address indices, and its
+    /// address is the first index in that range, so every device holding the same seed phrase
+    /// derives the same address for the same account.
+    pub fn address_for_account(&self, account: u32) -> (u64, Address) {
+        let index = u64::from(account) * ADDRESSES_PER_ACCOUNT;
+        let (address, _dtk) = self
+            .spend_key
+            .full_viewing_key()
+            .incoming()
+            .payment_address(index.into());
+        (index, address)
+    }
+
+    /// Check that a payment of `value` to the address with the given `index` would be detected
+    /// by this wallet, by building a dummy output to that address and trial-decrypting it.
+    ///
+    /// This is a self-test of address derivation, e.g. before sharing an address.
+    pub fn simulate_receive<R: RngCore + CryptoRng>(
+        &self,
+        index: u64,
+        value: Value,
+        mut rng: R,
+    ) -> bool {
+        let fvk = self.spend_key.full_viewing_key();
+        let (address, _dtk) = fvk.incoming().payment_address(index.into());
+
+        let output = OutputPlan::new(&mut rng, value, address, MemoPlaintext::default())
+            .output(fvk.outgoing());
+
+        match output.body.note_payload.trial_decrypt(fvk) {
+            Some(note) => {
+                note.value() == value
+                    && fvk.incoming().index_for_diversifier(&note.diversifier())
+                        == AddressIndex::from(index)
+            }
+            None => false,
+        }
+    }
+}
+
+/// The cipher keyed by `passphrase`, stretched with Argon2id under the given `params`.
+fn passphrase_cipher(
+    passphrase: &str,
+    salt: &[u8],
+    params: Params,
+) -> anyhow::Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("key derivation failed: {}", e))?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::{keys::SeedPhrase, Value, STAKING_TOKEN_ASSET_ID};
+    use rand_core::OsRng;
+
+    use super::{KeyStore, ENCRYPTED_MAGIC, ENCRYPTED_VERSION, HEADER_LEN_BYTES};
+
+    #[test]
+    fn simulate_receive_detects_owned_address() {
+        let key_store = KeyStore::from_seed_phrase(SeedPhrase::generate(&mut OsRng));
+        let value = Value {
+            amount: 1,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        assert!(key_store.simulate_receive(0, value, OsRng));
+        assert!(key_store.simulate_receive(7, value, OsRng));
+    }
+
+    #[test]
+    fn address_for_account_is_deterministic() {
+        let seed_phrase = SeedPhrase::generate(&mut OsRng);
+        let key_store = KeyStore::from_seed_phrase(SeedPhrase(seed_phrase.0.clone()));
+        let other_device = KeyStore::from_seed_phrase(seed_phrase);
+
+        let (index, address) = key_store.address_for_account(3);
+        assert_eq!(other_device.address_for_account(3), (index, address));
+        assert_ne!(key_store.address_for_account(4).1, address);
+    }
+
+    #[test]
+    fn encrypted_wallet_round_trips() {
+        let key_store = KeyStore::from_seed_phrase(SeedPhrase::generate(&mut OsRng));
+        let encrypted = key_store.encrypt("correct horse", OsRng).unwrap();
+
+        let decrypted = KeyStore::decrypt(&encrypted, "correct horse").unwrap();
+        assert_eq!(
+            decrypted.spend_key.full_viewing_key().hash(),
+            key_store.spend_key.full_viewing_key().hash()
+        );
+    }
+
+    #[test]
+    fn encrypted_wallet_rejects_wrong_passphrase_and_corruption() {
+        let key_store = KeyStore::from_seed_phrase(SeedPhrase::generate(&mut OsRng));
+        let mut encrypted = key_store.encrypt("correct horse", OsRng).unwrap();
+
+        assert!(KeyStore::decrypt(&encrypted, "battery staple").is_err());
+        assert!(KeyStore::decrypt(&encrypted[..10], "correct horse").is_err());
+
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        assert!(KeyStore::decrypt(&encrypted, "correct horse").is_err());
+    }
+
+    #[test]
+    fn encrypted_wallet_rejects_corrupted_header() {
+        let key_store = KeyStore::from_seed_phrase(SeedPhrase::generate(&mut OsRng));
+        let encrypted = key_store.encrypt("correct horse", OsRng).unwrap();
+
+        // The KDF parameters, salt and nonce are all authenticated.
+        let params_start = ENCRYPTED_MAGIC.len() + 1;
+        for i in [params_start + 3, params_start + 12, HEADER_LEN_BYTES - 1] {
+            let mut corrupted = encrypted.clone();
+            corrupted[i] ^= 1;
+            assert!(KeyStore::decrypt(&corrupted, "correct horse").is_err());
+        }
+
+        let mut unknown_version = encrypted;
+        unknown_version[ENCRYPTED_MAGIC.len()] = ENCRYPTED_VERSION + 1;
+        assert_eq!(
+            KeyStore::decrypt(&unknown_version, "correct horse")
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "unsupported encrypted wallet version {}",
+                ENCRYPTED_VERSION + 1
+            )
+        );
+    }
+
+    #[test]
+    fn ephemeral_wallet_refuses_to_serialize() {
+        let key_store = KeyStore::from_seed_phrase(SeedPhrase::generate(&mut OsRng));
+        assert!(serde_json::to_vec(&key_store).is_ok());
+
+        let key_store = key_store.into_ephemeral();
+        assert!(serde_json::to_vec(&key_store).is_err());
+        assert!(key_store.encrypt("correct horse", OsRng).is_err());
     }
 }
@@ -6,4 +6,10 @@ mod key_store;
 pub use build::build_transaction;
 pub use key_store::KeyStore;
 
+pub mod fee;
+pub mod offline;
 pub mod plan;
+pub mod selection;
+
+#[cfg(test)]
+mod test_utils;
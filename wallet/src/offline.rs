@@ -0,0 +1,131 @@
+//! Support for signing transactions on an offline (air-gapped) device.
+//!
+//! The online wallet plans a transaction and exports it with [`export_unsigned`]; the offline
+//! wallet, which holds the spend key, authorizes it with [`sign_unsigned`]; and the online wallet
+//! then builds the final transaction with [`import_signed`]. Both files are protobuf-encoded, as a
+//! [`TransactionPlan`] and an [`AuthorizationData`] respectively.
+
+use anyhow::Result;
+use penumbra_crypto::{keys::SpendKey, FullViewingKey};
+use penumbra_proto::{view::WitnessRequest, Protobuf};
+use penumbra_transaction::{plan::TransactionPlan, AuthorizationData, Transaction};
+use penumbra_view::ViewClient;
+use rand_core::{CryptoRng, RngCore};
+
+/// Encode a transaction plan for signing on an offline device.
+pub fn export_unsigned(plan: &TransactionPlan) -> Vec<u8> {
+    plan.encode_to_vec()
+}
+
+/// Authorize an exported transaction plan with the given spend key, returning the encoded
+/// authorization data to bring back to the online wallet.
+pub fn sign_unsigned<R: RngCore + CryptoRng>(
+    unsigned: &[u8],
+    sk: &SpendKey,
+    rng: R,
+) -> Result<Vec<u8>> {
+    let plan = TransactionPlan::decode(unsigned)?;
+    Ok(plan.authorize(rng, sk).encode_to_vec())
+}
+
+/// Build the transaction for `plan` using authorization data produced offline by
+/// [`sign_unsigned`].
+pub async fn import_signed<V, R>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    mut rng: R,
+    plan: TransactionPlan,
+    signed: &[u8],
+) -> Result<Transaction>
+where
+    V: ViewClient,
+    R: RngCore + CryptoRng,
+{
+    let auth_data = AuthorizationData::decode(signed)?;
+
+    // Make sure the signatures are for this plan, rather than some other exported plan.
+    if auth_data.auth_hash != plan.auth_hash(fvk) {
+        return Err(anyhow::anyhow!(
+            "authorization data was not produced for this transaction plan"
+        ));
+    }
+
+    let witness_data = view
+        .witness(WitnessRequest {
+            fvk_hash: Some(fvk.hash().into()),
+            note_commitments: plan
+                .spend_plans()
+                .map(|spend| spend.note.commit().into())
+                .collect(),
+        })
+        .await?;
+
+    plan.build(&mut rng, fvk, auth_data, witness_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_component::{shielded_pool::ShieldedPool, Component, Context};
+    use penumbra_crypto::{
+        keys::{SeedPhrase, SpendKey},
+        Value, STAKING_TOKEN_ASSET_ID,
+    };
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::{plan, test_utils::MockView};
+
+    async fn plan_send(fvk: &FullViewingKey, view: &mut MockView, amount: u64) -> TransactionPlan {
+        let (dest_address, _dtk) = fvk.incoming().payment_address(1u64.into());
+        plan::send(
+            fvk,
+            view,
+            OsRng,
+            &[Value {
+                amount,
+                asset_id: *STAKING_TOKEN_ASSET_ID,
+            }],
+            0,
+            dest_address,
+            plan::SendOptions::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn offline_signing_round_trip() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let mut view = MockView::new(fvk, [(0, 100)]);
+        let plan = plan_send(fvk, &mut view, 60).await;
+
+        let unsigned = export_unsigned(&plan);
+        let signed = sign_unsigned(&unsigned, &sk, OsRng).unwrap();
+        let transaction = import_signed(fvk, &mut view, OsRng, plan, &signed)
+            .await
+            .unwrap();
+
+        assert!(ShieldedPool::check_tx_stateless(Context::new(), &transaction).is_ok());
+        assert_eq!(transaction.spent_nullifiers().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn signatures_for_another_plan_are_rejected() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let mut view = MockView::new(fvk, [(0, 100)]);
+        let plan = plan_send(fvk, &mut view, 60).await;
+        let other_plan = plan_send(fvk, &mut view, 50).await;
+
+        let signed = sign_unsigned(&export_unsigned(&other_plan), &sk, OsRng).unwrap();
+        let error = import_signed(fvk, &mut view, OsRng, plan, &signed)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "authorization data was not produced for this transaction plan"
+        );
+    }
+}
@@ -1,11 +1,15 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 
 use anyhow::Result;
 use penumbra_component::stake::rate::RateData;
 use penumbra_component::stake::validator;
 use penumbra_crypto::{
-    asset::Denom, keys::AddressIndex, memo::MemoPlaintext, transaction::Fee, Address,
-    DelegationToken, FullViewingKey, Value, STAKING_TOKEN_ASSET_ID, STAKING_TOKEN_DENOM,
+    asset::{self, Denom},
+    keys::AddressIndex,
+    memo::MemoPlaintext,
+    note,
+    transaction::Fee,
+    Address, DelegationToken, FullViewingKey, Value, STAKING_TOKEN_ASSET_ID, STAKING_TOKEN_DENOM,
 };
 use penumbra_proto::view::NotesRequest;
 use penumbra_transaction::plan::{ActionPlan, OutputPlan, SpendPlan, TransactionPlan};
@@ -13,6 +17,167 @@ use penumbra_view::{NoteRecord, ViewClient};
 use rand_core::{CryptoRng, RngCore};
 use tracing::instrument;
 
+use crate::{fee::FeeChoice, selection};
+
+/// The error returned by [`require_synced_within`] when the view service is too far behind the
+/// chain tip to plan a transaction against a recent anchor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaleSync {
+    /// The number of blocks the view service is behind the chain tip.
+    pub behind: u64,
+}
+
+impl std::fmt::Display for StaleSync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "view service is {} blocks behind the chain tip; sync before planning a transaction",
+            self.behind
+        )
+    }
+}
+
+impl std::error::Error for StaleSync {}
+
+/// Check that the view service has synced to within `max_behind` blocks of `chain_tip`, failing
+/// with [`StaleSync`] otherwise.
+///
+/// Transactions planned against a stale view may be rejected, e.g. for spending notes which were
+/// already spent, so this should be checked before planning; [`send`] checks it when given
+/// [`SendOptions::require_synced`].
+pub async fn require_synced_within<V: ViewClient>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    chain_tip: u64,
+    max_behind: u64,
+) -> Result<()> {
+    let sync_height = view.status(fvk.hash()).await?.sync_height;
+    check_synced_within(sync_height, chain_tip, max_behind)?;
+    Ok(())
+}
+
+fn check_synced_within(sync_height: u64, chain_tip: u64, max_behind: u64) -> Result<(), StaleSync> {
+    let behind = chain_tip.saturating_sub(sync_height);
+    if behind > max_behind {
+        Err(StaleSync { behind })
+    } else {
+        Ok(())
+    }
+}
+
+/// A requirement that the view service has synced to within `max_behind` blocks of `chain_tip`,
+/// as checked by [`require_synced_within`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncRequirement {
+    pub chain_tip: u64,
+    pub max_behind: u64,
+}
+
+/// An error planning a payment, which callers can match on by downcasting the returned
+/// [`anyhow::Error`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    /// Our unspent notes of `denom` total only `available`, less than the `needed` amount.
+    InsufficientBalance {
+        denom: String,
+        needed: u64,
+        available: u64,
+    },
+    /// The denomination of the asset to pay is unknown to the view service.
+    UnknownDenomination(asset::Id),
+    /// No combination of our unspent notes of `denom` sums to exactly the `target` amount, as
+    /// needed to return an exact amount of change.
+    NoExactCombination { denom: String, target: u64 },
+    /// Covering the `needed` amount of `denom` would spend more notes, or notes sent to more
+    /// addresses, than the privacy `budget` allows.
+    PrivacyBudgetExceeded {
+        denom: String,
+        needed: u64,
+        budget: selection::PrivacyBudget,
+    },
+    /// The address to pay couldn't be parsed.
+    InvalidAddress(String),
+}
+
+impl std::fmt::Display for PlanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanError::InsufficientBalance {
+                denom,
+                needed,
+                available,
+            } => write!(
+                f,
+                "requested amount of {} {} exceeds total of {}",
+                needed, denom, available
+            ),
+            PlanError::UnknownDenomination(asset_id) => {
+                write!(f, "unknown denomination for asset id {}", asset_id)
+            }
+            PlanError::NoExactCombination { denom, target } => write!(
+                f,
+                "no combination of notes sums to exactly {} {}",
+                target, denom
+            ),
+            PlanError::PrivacyBudgetExceeded {
+                denom,
+                needed,
+                budget,
+            } => write!(
+                f,
+                "cannot cover {} {} with at most {} notes from at most {} addresses; consider sweeping notes together first",
+                needed, denom, budget.max_notes, budget.max_addresses
+            ),
+            PlanError::InvalidAddress(address) => write!(f, "address {} is invalid", address),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// The error returned by [`require_chain_id`] when the view service is synced to a different
+/// chain than the one a transaction is meant for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainIdMismatch {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for ChainIdMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "expected to plan a transaction for chain {}, but the view service is synced to chain {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChainIdMismatch {}
+
+/// Check that the view service is synced to the chain with the given `expected` chain ID, failing
+/// with [`ChainIdMismatch`] otherwise.
+///
+/// Plans take their chain ID from the view service's chain parameters, so this should be checked
+/// before planning, to avoid building a transaction for the wrong network; [`send`] checks it
+/// when given [`SendOptions::expected_chain_id`].
+pub async fn require_chain_id<V: ViewClient>(view: &mut V, expected: &str) -> Result<()> {
+    let actual = view.chain_params().await?.chain_id;
+    check_chain_id(expected, actual)?;
+    Ok(())
+}
+
+fn check_chain_id(expected: &str, actual: String) -> Result<(), ChainIdMismatch> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(ChainIdMismatch {
+            expected: expected.to_string(),
+            actual,
+        })
+    }
+}
+
 pub async fn validator_definition<V, R>(
     fvk: &FullViewingKey,
     view: &mut V,
@@ -60,6 +225,14 @@ where
         plan.actions
             .push(SpendPlan::new(&mut rng, note_record.note, note_record.position).into());
     }
+    if spent_amount < spend_amount {
+        return Err(PlanError::InsufficientBalance {
+            denom: STAKING_TOKEN_DENOM.to_string(),
+            needed: spend_amount,
+            available: spent_amount,
+        }
+        .into());
+    }
     // Add a change note if we have change left over:
     let change_amount = spent_amount - spend_amount;
     // TODO: support dummy notes, and produce a change output unconditionally.
@@ -73,7 +246,7 @@ where
                     asset_id: *STAKING_TOKEN_ASSET_ID,
                 },
                 self_address,
-                MemoPlaintext::default(),
+                MemoPlaintext::change(),
             )
             .into(),
         );
@@ -151,11 +324,12 @@ where
     }
 
     if spent_amount < spend_amount {
-        return Err(anyhow::anyhow!(
-            "not enough notes to delegate: wanted to delegate {}, have {}",
-            spend_amount,
-            spent_amount
-        ));
+        return Err(PlanError::InsufficientBalance {
+            denom: STAKING_TOKEN_DENOM.to_string(),
+            needed: spend_amount,
+            available: spent_amount,
+        }
+        .into());
     }
 
     // Add a change note if we have change left over:
@@ -172,7 +346,7 @@ where
                     asset_id: *STAKING_TOKEN_ASSET_ID,
                 },
                 self_address,
-                MemoPlaintext::default(),
+                MemoPlaintext::change(),
             )
             .into(),
         );
@@ -212,14 +386,14 @@ where
     // avoid any unnecessary change outputs, so we pay fees out of the
     // unbonded amount.
     let unbonded_amount = rate_data.unbonded_amount(delegation_amount);
-    let output_amount = unbonded_amount.checked_sub(fee).ok_or_else(|| {
-        anyhow::anyhow!(
-            "unbonded amount {} from delegation amount {} is insufficient to pay fees {}",
-            unbonded_amount,
-            delegation_amount,
-            fee
-        )
-    })?;
+    let output_amount =
+        unbonded_amount
+            .checked_sub(fee)
+            .ok_or_else(|| PlanError::InsufficientBalance {
+                denom: STAKING_TOKEN_DENOM.to_string(),
+                needed: fee,
+                available: unbonded_amount,
+            })?;
 
     let mut plan = TransactionPlan {
         chain_id: chain_params.chain_id,
@@ -254,33 +428,178 @@ where
     }
 
     if spent_amount < spend_amount {
-        Err(anyhow::anyhow!(
-            "not enough delegated tokens to undelegate: wanted to undelegate {}, have {}",
-            spend_amount,
-            spent_amount,
-        ))?;
+        return Err(PlanError::InsufficientBalance {
+            denom: DelegationToken::new(rate_data.identity_key).to_string(),
+            needed: spend_amount,
+            available: spent_amount,
+        }
+        .into());
     }
 
     Ok(plan)
 }
 
-#[allow(clippy::too_many_arguments)]
-#[instrument(skip(fvk, view, rng, values, fee, dest_address, source_address, tx_memo))]
+/// Options for planning a payment with [`send`] or [`send_to_many`], beyond the payments
+/// themselves and the fee.
+///
+/// The default spends from any of our addresses, attaches no memo, and selects notes with the
+/// default [`CoinSelectionStrategy`](selection::CoinSelectionStrategy), without any further
+/// constraints on the notes spent or the outputs created.
+#[derive(Clone, Debug, Default)]
+pub struct SendOptions {
+    /// The address index to spend from and to send change to, or any of our addresses if unset.
+    pub source_address: Option<u64>,
+    /// A memo to attach to the payment outputs, at most
+    /// [`MEMO_LEN_BYTES`](penumbra_crypto::memo::MEMO_LEN_BYTES) bytes long.
+    pub tx_memo: Option<String>,
+    /// The exact change to return for an asset, selecting its notes to sum to exactly the amount
+    /// required plus this change.
+    pub exact_change: Option<Value>,
+    /// The strategy selecting the notes to spend, for assets without `exact_change`.
+    pub strategy: selection::CoinSelectionStrategy,
+    /// The minimum value of an output: smaller payments are rejected, and smaller staking token
+    /// change is paid as fee rather than returned in a dust note.
+    pub min_output_value: Option<u64>,
+    /// A cap on the notes, and the addresses they were sent to, spent for each asset.
+    pub privacy_budget: Option<selection::PrivacyBudget>,
+    /// The chain the payment is meant for: if set, planning fails with [`ChainIdMismatch`] unless
+    /// the view service is synced to this chain.
+    pub expected_chain_id: Option<String>,
+    /// How closely the view service must be synced to the chain tip: if set, planning fails with
+    /// [`StaleSync`] when it's further behind.
+    pub require_synced: Option<SyncRequirement>,
+}
+
+/// Generate a new transaction plan sending `values` to `dest_address`.
+///
+/// This is [`send_to_many`] with a single recipient; see it for the meaning of the other
+/// arguments.
+#[instrument(skip(fvk, view, rng, values, fee, dest_address, options))]
 pub async fn send<V, R>(
     fvk: &FullViewingKey,
     view: &mut V,
-    mut rng: R,
+    rng: R,
     values: &[Value],
-    fee: u64,
+    fee: impl Into<FeeChoice>,
     dest_address: Address,
-    source_address: Option<u64>,
-    tx_memo: Option<String>,
+    options: SendOptions,
 ) -> Result<TransactionPlan, anyhow::Error>
 where
     V: ViewClient,
     R: RngCore + CryptoRng,
 {
-    tracing::debug!(?values, ?fee, ?dest_address, ?source_address, ?tx_memo);
+    let payments = values
+        .iter()
+        .map(|value| (dest_address, *value))
+        .collect::<Vec<_>>();
+
+    send_to_many(fvk, view, rng, &payments, fee, options).await
+}
+
+/// Generate a new transaction plan making each of the given `payments`, with one output per
+/// payment.
+///
+/// If [`SendOptions::tx_memo`] is set, it is attached to the payment outputs; change outputs
+/// always carry the change memo.
+///
+/// The notes spent for each asset are gathered across all the payments of that asset, and each
+/// asset gets at most one change output. Unless [`SendOptions::exact_change`] is set for an
+/// asset, its notes are selected using [`SendOptions::strategy`].
+///
+/// If [`SendOptions::min_output_value`] is set, payments of less than it are rejected, and
+/// staking token change of less than it is paid as fee rather than returned in a dust note.
+///
+/// If [`SendOptions::privacy_budget`] is set, the notes spent for each asset must fit within it.
+/// If the notes selected using the strategy don't, the notes are selected to fit the budget
+/// instead, and if no selection fits, this returns an error suggesting a sweep.
+///
+/// If [`SendOptions::exact_change`] is set, the notes spent for its asset are selected so that
+/// they sum to exactly the amount required plus the change, producing a change output of exactly
+/// that value; if no such combination of notes exists, this returns an error.
+///
+/// The `fee` is either an exact fee, or [`FeeChoice::Auto`], in which case it's estimated from the
+/// numbers of spends and outputs in the plan, re-planning with a higher fee until the plan pays
+/// at least its own estimate.
+///
+/// The returned plan's fee is its effective fee (see [`TransactionPlan::effective_fee`]), which
+/// may exceed the requested `fee` if some value is left unallocated by the change outputs.
+///
+/// Randomness is drawn from `rng` in a fixed order, so that the same `rng` state and view
+/// contents always produce the same plan: first an [`OutputPlan`] per payment, in order; then,
+/// for each asset in denomination order, the note selection (which draws only for
+/// [`CoinSelectionStrategy::Random`](selection::CoinSelectionStrategy::Random)) followed by a
+/// [`SpendPlan`] per selected note; and finally an [`OutputPlan`] per change output, in asset ID
+/// order. With [`FeeChoice::Auto`], this whole sequence repeats each time the plan is re-planned
+/// with a higher fee.
+#[instrument(skip(fvk, view, rng, payments, fee, options))]
+pub async fn send_to_many<V, R>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    mut rng: R,
+    payments: &[(Address, Value)],
+    fee: impl Into<FeeChoice>,
+    options: SendOptions,
+) -> Result<TransactionPlan, anyhow::Error>
+where
+    V: ViewClient,
+    R: RngCore + CryptoRng,
+{
+    if let Some(expected) = &options.expected_chain_id {
+        require_chain_id(view, expected).await?;
+    }
+    if let Some(SyncRequirement {
+        chain_tip,
+        max_behind,
+    }) = options.require_synced
+    {
+        require_synced_within(fvk, view, chain_tip, max_behind).await?;
+    }
+
+    let parameters = match fee.into() {
+        FeeChoice::Exact(fee) => {
+            return send_to_many_with_fee(fvk, view, rng, payments, fee, &options).await
+        }
+        FeeChoice::Auto(parameters) => parameters,
+    };
+
+    // Start from the smallest possible transaction: a spend and a change output besides the
+    // payments. Paying a higher fee can only require more spends, so the estimate only grows.
+    let mut fee = parameters.estimate_fee(1, payments.len() + 1);
+    loop {
+        let plan = send_to_many_with_fee(fvk, view, &mut rng, payments, fee, &options).await?;
+
+        let estimate =
+            parameters.estimate_fee(plan.spend_plans().count(), plan.output_plans().count());
+        if estimate <= fee {
+            return Ok(plan);
+        }
+        tracing::debug!(?fee, ?estimate, "re-planning with estimated fee");
+        fee = estimate;
+    }
+}
+
+async fn send_to_many_with_fee<V, R>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    mut rng: R,
+    payments: &[(Address, Value)],
+    fee: u64,
+    options: &SendOptions,
+) -> Result<TransactionPlan, anyhow::Error>
+where
+    V: ViewClient,
+    R: RngCore + CryptoRng,
+{
+    tracing::debug!(?payments, ?fee, ?options);
+    let SendOptions {
+        source_address,
+        tx_memo,
+        exact_change,
+        strategy,
+        min_output_value,
+        privacy_budget,
+        ..
+    } = options.clone();
     let memo = if let Some(input_memo) = tx_memo {
         input_memo.as_bytes().try_into()?
     } else {
@@ -295,31 +614,36 @@ where
         ..Default::default()
     };
 
+    // Refuse to create payments so small they'd only be dust to the recipient.
+    if let Some(min_output_value) = min_output_value {
+        if let Some((_, value)) = payments
+            .iter()
+            .find(|(_, value)| value.amount < min_output_value)
+        {
+            return Err(anyhow::anyhow!(
+                "payment of {} of asset {} is below the minimum output value of {}",
+                value.amount,
+                value.asset_id,
+                min_output_value
+            ));
+        }
+    }
+
     let assets = view.assets().await?;
     // Track totals of the output values rather than just processing
     // them individually, so we can plan the required spends.
-    let mut output_value = HashMap::<Denom, u64>::new();
-    for Value { amount, asset_id } in values {
+    let mut output_value = BTreeMap::<Denom, u64>::new();
+    for (_, Value { amount, asset_id }) in payments {
         let denom = assets
             .get(asset_id)
-            .ok_or_else(|| anyhow::anyhow!("unknown denomination for asset id {}", asset_id))?;
-        output_value.insert(denom.clone(), *amount);
+            .ok_or(PlanError::UnknownDenomination(*asset_id))?;
+        *output_value.entry(denom.clone()).or_default() += *amount;
     }
 
     // Add outputs for the funds we want to send:
-    for (denom, amount) in &output_value {
-        plan.actions.push(
-            OutputPlan::new(
-                &mut rng,
-                Value {
-                    amount: *amount,
-                    asset_id: denom.id(),
-                },
-                dest_address,
-                memo.clone(),
-            )
-            .into(),
-        );
+    for (dest_address, value) in payments {
+        plan.actions
+            .push(OutputPlan::new(&mut rng, *value, *dest_address, memo.clone()).into());
     }
 
     // The value we need to spend is the output value, plus fees.
@@ -330,6 +654,15 @@ where
             .or_default() += fee;
     }
 
+    // Track the values of every note we spend, and where to send each asset's change, so that
+    // change is computed per asset once all the spends are planned.
+    let required = value_to_spend
+        .iter()
+        .map(|(denom, amount)| (denom.id(), *amount))
+        .collect::<BTreeMap<_, _>>();
+    let mut spent_values = Vec::new();
+    let mut change_addresses = BTreeMap::new();
+
     // Add the required spends:
     for (denom, spend_amount) in value_to_spend {
         // Only produce an output if the amount is greater than zero
@@ -338,19 +671,93 @@ where
         }
 
         let source_index: Option<AddressIndex> = source_address.map(Into::into);
-        // Select a list of notes that provides at least the required amount.
-        let notes_to_spend = view
-            .notes(NotesRequest {
-                fvk_hash: Some(fvk.hash().into()),
-                asset_id: Some(denom.id().into()),
-                address_index: source_index.map(Into::into),
-                amount_to_spend: spend_amount,
-                include_spent: false,
-            })
-            .await?;
+        let exact_change = exact_change
+            .filter(|change| change.asset_id == denom.id())
+            .map(|change| change.amount);
+        let notes_to_spend = match exact_change {
+            // Select a list of notes that provides at least the required amount.
+            None => {
+                let mut candidates = view
+                    .notes(NotesRequest {
+                        fvk_hash: Some(fvk.hash().into()),
+                        asset_id: Some(denom.id().into()),
+                        address_index: source_index.map(Into::into),
+                        amount_to_spend: 0,
+                        include_spent: false,
+                    })
+                    .await?;
+                // Put the candidates in a canonical order, so that selections which break ties by
+                // the order of the notes are the same however the view returns them.
+                candidates.sort_by_key(|record| record.note_commitment.0.to_bytes());
+                let chosen = strategy
+                    .select(&candidates, spend_amount, &mut rng)
+                    .ok_or_else(|| PlanError::InsufficientBalance {
+                        denom: denom.to_string(),
+                        needed: spend_amount,
+                        available: candidates.iter().map(|record| record.note.amount()).sum(),
+                    })?;
+                let chosen = match privacy_budget {
+                    Some(budget) if !budget.allows(&address_amounts(&candidates), &chosen) => {
+                        budget
+                            .select(&address_amounts(&candidates), spend_amount)
+                            .map_err(|_| PlanError::PrivacyBudgetExceeded {
+                                denom: denom.to_string(),
+                                needed: spend_amount,
+                                budget,
+                            })?
+                    }
+                    _ => chosen,
+                };
+                chosen.into_iter().map(|i| candidates[i].clone()).collect()
+            }
+            // Select a list of notes that provides exactly the required amount plus change.
+            Some(change) => {
+                let target = spend_amount.checked_add(change).ok_or_else(|| {
+                    anyhow::anyhow!("exact change of {} {} overflows", change, denom)
+                })?;
+                let mut candidates = view
+                    .notes(NotesRequest {
+                        fvk_hash: Some(fvk.hash().into()),
+                        asset_id: Some(denom.id().into()),
+                        address_index: source_index.map(Into::into),
+                        amount_to_spend: 0,
+                        include_spent: false,
+                    })
+                    .await?;
+                // Put the candidates in a canonical order, so that selections which break ties by
+                // the order of the notes are the same however the view returns them.
+                candidates.sort_by_key(|record| record.note_commitment.0.to_bytes());
+                let amounts = candidates
+                    .iter()
+                    .map(|record| record.note.amount())
+                    .collect::<Vec<_>>();
+                let chosen =
+                    selection::exact_subset(&amounts, target, selection::MAX_EXACT_SUBSET_SIZE)
+                        .ok_or_else(|| PlanError::NoExactCombination {
+                            denom: denom.to_string(),
+                            target,
+                        })?;
+                if let Some(budget) = privacy_budget {
+                    if !budget.allows(&address_amounts(&candidates), &chosen) {
+                        return Err(PlanError::PrivacyBudgetExceeded {
+                            denom: denom.to_string(),
+                            needed: target,
+                            budget,
+                        }
+                        .into());
+                    }
+                }
+                chosen.into_iter().map(|i| candidates[i].clone()).collect()
+            }
+        };
         if notes_to_spend.is_empty() {
             // Shouldn't happen because the other side checks this, but just in case...
-            return Err(anyhow::anyhow!("not enough notes to spend",));
+            return Err(PlanError::InsufficientBalance {
+                denom: denom.to_string(),
+                needed: spend_amount,
+                available: 0,
+            }
+            .into());
         }
 
         let change_address_index: u64 = fvk
@@ -365,38 +772,58 @@ where
             .try_into()?;
 
         let (change_address, _dtk) = fvk.incoming().payment_address(change_address_index.into());
-        let spent: u64 = notes_to_spend
-            .iter()
-            .map(|note_record| note_record.note.amount())
-            .sum();
+        change_addresses.insert(denom.id(), change_address);
 
         // Spend each of the notes we selected.
         for note_record in notes_to_spend {
+            spent_values.push(note_record.note.value());
             plan.actions
                 .push(SpendPlan::new(&mut rng, note_record.note, note_record.position).into());
         }
+    }
 
-        // Find out how much change we have and whether to add a change output.
-        let change = spent - spend_amount;
-        if change > 0 {
-            plan.actions.push(
-                OutputPlan::new(
-                    &mut rng,
-                    Value {
-                        amount: change,
-                        asset_id: denom.id(),
-                    },
-                    change_address,
-                    MemoPlaintext::default(),
-                )
-                .into(),
-            );
+    // Add one change output for each asset with change left over.
+    let mut change = selection::change_per_asset(&spent_values, &required)?;
+    if let Some(min_output_value) = min_output_value {
+        // Any dust change removed here is included in the effective fee below.
+        let absorbed = selection::absorb_dust_change(&mut change, min_output_value);
+        if absorbed > 0 {
+            tracing::debug!(?absorbed, "paying dust change as fee");
         }
     }
+    for (asset_id, amount) in change {
+        let change_address = change_addresses
+            .get(&asset_id)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("no change address for asset {}", asset_id))?;
+        plan.actions.push(
+            OutputPlan::new(
+                &mut rng,
+                Value { amount, asset_id },
+                change_address,
+                MemoPlaintext::change(),
+            )
+            .into(),
+        );
+    }
+
+    // Report the fee the transaction actually pays, including any value the change outputs
+    // leave unallocated, rather than just the fee that was requested.
+    if let Some(effective_fee) = plan.effective_fee() {
+        plan.fee = Fee(effective_fee);
+    }
 
     Ok(plan)
 }
 
+/// The address index and amount of each of the `notes`, as used by [`selection::PrivacyBudget`].
+fn address_amounts(notes: &[NoteRecord]) -> Vec<(AddressIndex, u64)> {
+    notes
+        .iter()
+        .map(|record| (record.address_index, record.note.amount()))
+        .collect()
+}
+
 #[instrument(skip(fvk, view, rng))]
 pub async fn sweep<V, R>(
     fvk: &FullViewingKey,
@@ -474,3 +901,1005 @@ where
 
     Ok(plans)
 }
+
+/// Plan a sequence of transactions consolidating the notes of `asset_id`, each spending at most
+/// `max_spends` notes, for wallets holding more notes than fit in a single transaction.
+///
+/// As with [`sweep`], notes are only consolidated with other notes sent to the same address. When
+/// consolidating the staking token, each transaction pays a fee of `fee_rate` per action out of
+/// the consolidated value; other assets are consolidated without a fee.
+#[instrument(skip(fvk, view, rng))]
+pub async fn consolidate<V, R>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    mut rng: R,
+    asset_id: asset::Id,
+    max_spends: usize,
+    fee_rate: u64,
+) -> Result<Vec<TransactionPlan>, anyhow::Error>
+where
+    V: ViewClient,
+    R: RngCore + CryptoRng,
+{
+    let chain_id = view.chain_params().await?.chain_id;
+
+    let notes = view
+        .notes(NotesRequest {
+            fvk_hash: Some(fvk.hash().into()),
+            asset_id: Some(asset_id.into()),
+            ..Default::default()
+        })
+        .await?;
+
+    let mut notes_by_addr: BTreeMap<AddressIndex, Vec<NoteRecord>> = BTreeMap::new();
+    for record in notes {
+        notes_by_addr
+            .entry(record.address_index)
+            .or_default()
+            .push(record);
+    }
+
+    let mut plans = Vec::new();
+
+    for (index, mut records) in notes_by_addr {
+        let (addr, _dtk) = fvk.incoming().payment_address(index);
+
+        // Consolidate the smallest notes first.
+        records.sort_by_key(|record| record.note.amount());
+
+        for batch in selection::consolidation_batches(records.len(), max_spends) {
+            let group = &records[batch];
+
+            // Each transaction has a spend for each note, plus one output.
+            let fee = if asset_id == *STAKING_TOKEN_ASSET_ID {
+                (group.len() as u64 + 1) * fee_rate
+            } else {
+                0
+            };
+            let total: u64 = group.iter().map(|record| record.note.amount()).sum();
+            let amount = total
+                .checked_sub(fee)
+                .ok_or_else(|| PlanError::InsufficientBalance {
+                    denom: STAKING_TOKEN_DENOM.to_string(),
+                    needed: fee,
+                    available: total,
+                })?;
+
+            let mut plan = TransactionPlan {
+                chain_id: chain_id.clone(),
+                fee: Fee(fee),
+                ..Default::default()
+            };
+            for record in group {
+                plan.actions
+                    .push(SpendPlan::new(&mut rng, record.note.clone(), record.position).into());
+            }
+            plan.actions.push(
+                OutputPlan::new(
+                    &mut rng,
+                    Value { amount, asset_id },
+                    addr,
+                    MemoPlaintext::default(),
+                )
+                .into(),
+            );
+
+            plans.push(plan);
+        }
+    }
+
+    Ok(plans)
+}
+
+/// Plan self-send transactions re-splitting our notes of `asset_id` into notes of the given
+/// `standard_values` (e.g. 1, 10 and 100), for wallets that want to hold standard-valued notes.
+///
+/// Notes which already hold a standard value are left as they are; the rest of the notes sent to
+/// each address are spent together, and their value re-split as by
+/// [`selection::split_into_standard_values`], with any value that can't be split into standard
+/// values kept as a single note. As with [`consolidate`], rebalancing the staking token pays a fee
+/// of `fee_rate` per action out of the rebalanced value.
+#[instrument(skip(fvk, view, rng))]
+pub async fn rebalance<V, R>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    mut rng: R,
+    asset_id: asset::Id,
+    standard_values: &[u64],
+    fee_rate: u64,
+) -> Result<Vec<TransactionPlan>, anyhow::Error>
+where
+    V: ViewClient,
+    R: RngCore + CryptoRng,
+{
+    let chain_id = view.chain_params().await?.chain_id;
+
+    let notes = view
+        .notes(NotesRequest {
+            fvk_hash: Some(fvk.hash().into()),
+            asset_id: Some(asset_id.into()),
+            ..Default::default()
+        })
+        .await?;
+
+    let mut notes_by_addr: BTreeMap<AddressIndex, Vec<NoteRecord>> = BTreeMap::new();
+    for record in notes {
+        if !standard_values.contains(&record.note.amount()) {
+            notes_by_addr
+                .entry(record.address_index)
+                .or_default()
+                .push(record);
+        }
+    }
+
+    let mut plans = Vec::new();
+
+    for (index, records) in notes_by_addr {
+        let (addr, _dtk) = fvk.incoming().payment_address(index);
+
+        let total: u64 = records.iter().map(|record| record.note.amount()).sum();
+        // The fee depends on the number of outputs, which depends on the value left after the
+        // fee, so estimate the outputs from the total, which can only overestimate the fee.
+        let fee = if asset_id == *STAKING_TOKEN_ASSET_ID {
+            let (amounts, remainder) =
+                selection::split_into_standard_values(total, standard_values);
+            let outputs = amounts.len() + usize::from(remainder > 0);
+            (records.len() + outputs) as u64 * fee_rate
+        } else {
+            0
+        };
+        let amount = total
+            .checked_sub(fee)
+            .ok_or_else(|| PlanError::InsufficientBalance {
+                denom: STAKING_TOKEN_DENOM.to_string(),
+                needed: fee,
+                available: total,
+            })?;
+
+        let (mut amounts, remainder) =
+            selection::split_into_standard_values(amount, standard_values);
+        if remainder > 0 {
+            amounts.push(remainder);
+        }
+
+        let mut plan = TransactionPlan {
+            chain_id: chain_id.clone(),
+            fee: Fee(fee),
+            ..Default::default()
+        };
+        for record in &records {
+            plan.actions
+                .push(SpendPlan::new(&mut rng, record.note.clone(), record.position).into());
+        }
+        for amount in amounts {
+            plan.actions.push(
+                OutputPlan::new(
+                    &mut rng,
+                    Value { amount, asset_id },
+                    addr,
+                    MemoPlaintext::default(),
+                )
+                .into(),
+            );
+        }
+
+        tracing::debug!(?plan);
+        plans.push(plan);
+    }
+
+    Ok(plans)
+}
+
+/// Plan a transaction sending every one of our unspent notes of `asset_id` to `dest_address`, in
+/// a single output of their total value less `fee`, with no change output.
+///
+/// Since the fee is paid out of the swept value, a nonzero fee can only be paid when sweeping the
+/// staking token, and the notes must total more than the fee.
+#[instrument(skip(fvk, view, rng, dest_address))]
+pub async fn sweep_asset<V, R>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    rng: R,
+    asset_id: asset::Id,
+    dest_address: Address,
+    fee: u64,
+) -> Result<TransactionPlan, anyhow::Error>
+where
+    V: ViewClient,
+    R: RngCore + CryptoRng,
+{
+    let chain_id = view.chain_params().await?.chain_id;
+
+    let records = view
+        .notes(NotesRequest {
+            fvk_hash: Some(fvk.hash().into()),
+            asset_id: Some(asset_id.into()),
+            ..Default::default()
+        })
+        .await?;
+
+    sweep_asset_plan(rng, chain_id, &records, asset_id, dest_address, fee)
+}
+
+fn sweep_asset_plan<R: RngCore + CryptoRng>(
+    mut rng: R,
+    chain_id: String,
+    records: &[NoteRecord],
+    asset_id: asset::Id,
+    dest_address: Address,
+    fee: u64,
+) -> Result<TransactionPlan, anyhow::Error> {
+    if fee > 0 && asset_id != *STAKING_TOKEN_ASSET_ID {
+        return Err(anyhow::anyhow!(
+            "can't pay a fee out of swept asset {}",
+            asset_id
+        ));
+    }
+
+    let total: u64 = records.iter().map(|record| record.note.amount()).sum();
+    // The output must carry some value after the fee is paid.
+    if total <= fee {
+        return Err(PlanError::InsufficientBalance {
+            denom: STAKING_TOKEN_DENOM.to_string(),
+            needed: fee + 1,
+            available: total,
+        }
+        .into());
+    }
+
+    let mut plan = TransactionPlan {
+        chain_id,
+        fee: Fee(fee),
+        ..Default::default()
+    };
+    for record in records {
+        plan.actions
+            .push(SpendPlan::new(&mut rng, record.note.clone(), record.position).into());
+    }
+    plan.actions.push(
+        OutputPlan::new(
+            &mut rng,
+            Value {
+                amount: total - fee,
+                asset_id,
+            },
+            dest_address,
+            MemoPlaintext::default(),
+        )
+        .into(),
+    );
+
+    Ok(plan)
+}
+
+/// Find unspent notes of `asset_id` which sum to exactly `amount`, so that it can be paid (e.g.
+/// as a refund) without a change output, returning `None` if no such notes were found.
+///
+/// A single note of exactly `amount` is found if there is one. Otherwise, at most
+/// [`selection::MAX_EXACT_SUBSET_SIZE`] notes are combined, using the bounded search of
+/// [`selection::exact_subset`], so this may return `None` even if some larger combination exists.
+#[instrument(skip(fvk, view))]
+pub async fn exact_payment_notes<V: ViewClient>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    asset_id: asset::Id,
+    amount: u64,
+) -> Result<Option<Vec<note::Commitment>>> {
+    let records = view
+        .notes(NotesRequest {
+            fvk_hash: Some(fvk.hash().into()),
+            asset_id: Some(asset_id.into()),
+            ..Default::default()
+        })
+        .await?;
+
+    let amounts = records
+        .iter()
+        .map(|record| record.note.amount())
+        .collect::<Vec<_>>();
+
+    Ok(
+        selection::exact_subset(&amounts, amount, selection::MAX_EXACT_SUBSET_SIZE).map(|chosen| {
+            chosen
+                .into_iter()
+                .map(|i| records[i].note_commitment)
+                .collect()
+        }),
+    )
+}
+
+/// A suggested self-send merging our smallest notes of an asset into one, with enough detail for
+/// a client to prompt the user to approve it.
+#[derive(Clone, Debug)]
+pub struct ConsolidationPlan {
+    pub plan: TransactionPlan,
+    /// The number of unspent notes of the asset held before the consolidation.
+    pub notes_before: usize,
+    /// The number of unspent notes of the asset held after the consolidation.
+    pub notes_after: usize,
+    pub fee: u64,
+}
+
+/// Suggest a consolidation of our notes of `asset_id` if we hold more than `max_notes` of them,
+/// merging the smallest notes into one to bring the count down to `max_notes`.
+///
+/// As with [`consolidate`], notes are only merged with other notes sent to the same address, so
+/// the notes merged are those of the address holding the most notes, and if our notes are spread
+/// across addresses, more than `max_notes` may remain afterwards. When consolidating the staking
+/// token, the transaction pays a fee of `fee_rate` per action out of the merged value.
+#[instrument(skip(fvk, view, rng))]
+pub async fn suggest_consolidation<V, R>(
+    fvk: &FullViewingKey,
+    view: &mut V,
+    rng: R,
+    asset_id: asset::Id,
+    max_notes: usize,
+    fee_rate: u64,
+) -> Result<Option<ConsolidationPlan>, anyhow::Error>
+where
+    V: ViewClient,
+    R: RngCore + CryptoRng,
+{
+    let chain_id = view.chain_params().await?.chain_id;
+
+    let notes = view
+        .notes(NotesRequest {
+            fvk_hash: Some(fvk.hash().into()),
+            asset_id: Some(asset_id.into()),
+            ..Default::default()
+        })
+        .await?;
+    let notes_before = notes.len();
+
+    let mut notes_by_addr: BTreeMap<AddressIndex, Vec<NoteRecord>> = BTreeMap::new();
+    for record in notes {
+        notes_by_addr
+            .entry(record.address_index)
+            .or_default()
+            .push(record);
+    }
+    let (index, mut records) = match notes_by_addr
+        .into_iter()
+        .max_by_key(|(_, records)| records.len())
+    {
+        Some(busiest) => busiest,
+        None => return Ok(None),
+    };
+
+    let count = match selection::notes_to_merge(notes_before, max_notes) {
+        Some(count) => count.min(records.len()),
+        None => return Ok(None),
+    };
+    if count < 2 {
+        return Ok(None);
+    }
+
+    // Merge the smallest notes.
+    records.sort_by_key(|record| record.note.amount());
+    records.truncate(count);
+
+    // The transaction has a spend for each note, plus one output.
+    let fee = if asset_id == *STAKING_TOKEN_ASSET_ID {
+        (count as u64 + 1) * fee_rate
+    } else {
+        0
+    };
+    let (addr, _dtk) = fvk.incoming().payment_address(index);
+    let plan = sweep_asset_plan(rng, chain_id, &records, asset_id, addr, fee)?;
+
+    Ok(Some(ConsolidationPlan {
+        plan,
+        notes_before,
+        notes_after: notes_before - count + 1,
+        fee,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::keys::{SeedPhrase, SpendKey};
+    use penumbra_proto::Protobuf;
+    use rand::{rngs::StdRng, SeedableRng};
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::test_utils::{note_record, CountingRng, MockView};
+
+    #[test]
+    fn stale_sync_beyond_tolerance_is_rejected() {
+        assert_eq!(
+            check_synced_within(900, 1000, 10),
+            Err(StaleSync { behind: 100 })
+        );
+        assert_eq!(check_synced_within(995, 1000, 10), Ok(()));
+        assert_eq!(check_synced_within(1000, 1000, 0), Ok(()));
+    }
+
+    #[test]
+    fn plan_errors_can_be_matched() {
+        let error = anyhow::Error::from(PlanError::InsufficientBalance {
+            denom: "upenumbra".to_string(),
+            needed: 100,
+            available: 60,
+        });
+        assert_eq!(
+            error.to_string(),
+            "requested amount of 100 upenumbra exceeds total of 60"
+        );
+        assert!(matches!(
+            error.downcast_ref::<PlanError>(),
+            Some(PlanError::InsufficientBalance {
+                needed: 100,
+                available: 60,
+                ..
+            })
+        ));
+
+        let error = anyhow::Error::from(PlanError::UnknownDenomination(*STAKING_TOKEN_ASSET_ID));
+        assert!(matches!(
+            error.downcast_ref::<PlanError>(),
+            Some(PlanError::UnknownDenomination(asset_id)) if *asset_id == *STAKING_TOKEN_ASSET_ID
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_sends_return_plan_errors() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let mut view = MockView::new(fvk, [(0, 20), (1, 20)]);
+        let staking = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+
+        assert_eq!(
+            plan_error(fvk, &mut view, staking(50), SendOptions::default()).await,
+            PlanError::InsufficientBalance {
+                denom: STAKING_TOKEN_DENOM.to_string(),
+                needed: 50,
+                available: 40,
+            }
+        );
+
+        let unknown = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+        assert_eq!(
+            plan_error(
+                fvk,
+                &mut view,
+                Value {
+                    amount: 1,
+                    asset_id: unknown
+                },
+                SendOptions::default()
+            )
+            .await,
+            PlanError::UnknownDenomination(unknown)
+        );
+
+        assert_eq!(
+            plan_error(
+                fvk,
+                &mut view,
+                staking(10),
+                SendOptions {
+                    exact_change: Some(staking(5)),
+                    ..Default::default()
+                }
+            )
+            .await,
+            PlanError::NoExactCombination {
+                denom: STAKING_TOKEN_DENOM.to_string(),
+                target: 15,
+            }
+        );
+
+        let budget = selection::PrivacyBudget {
+            max_notes: 1,
+            max_addresses: 1,
+        };
+        assert_eq!(
+            plan_error(
+                fvk,
+                &mut view,
+                staking(30),
+                SendOptions {
+                    privacy_budget: Some(budget),
+                    ..Default::default()
+                }
+            )
+            .await,
+            PlanError::PrivacyBudgetExceeded {
+                denom: STAKING_TOKEN_DENOM.to_string(),
+                needed: 30,
+                budget,
+            }
+        );
+    }
+
+    /// The [`PlanError`] a payment of `payment` to an address of `fvk` fails with.
+    async fn plan_error(
+        fvk: &FullViewingKey,
+        view: &mut MockView,
+        payment: Value,
+        options: SendOptions,
+    ) -> PlanError {
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+        send(fvk, view, OsRng, &[payment], 0, dest_address, options)
+            .await
+            .unwrap_err()
+            .downcast::<PlanError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn mismatched_chain_id_is_rejected() {
+        assert_eq!(
+            check_chain_id("penumbra-testnet-a", "penumbra-testnet-b".to_string()),
+            Err(ChainIdMismatch {
+                expected: "penumbra-testnet-a".to_string(),
+                actual: "penumbra-testnet-b".to_string(),
+            })
+        );
+        assert_eq!(
+            check_chain_id("penumbra-testnet-a", "penumbra-testnet-a".to_string()),
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn send_checks_expected_chain_id() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let mut view = MockView::new(fvk, [(0, 20)]);
+        let payment = Value {
+            amount: 10,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+        let options = |chain_id: &str| SendOptions {
+            expected_chain_id: Some(chain_id.to_string()),
+            ..Default::default()
+        };
+
+        let error = send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[payment],
+            0,
+            dest_address,
+            options("penumbra-other"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ChainIdMismatch>(),
+            Some(&ChainIdMismatch {
+                expected: "penumbra-other".to_string(),
+                actual: "penumbra-test".to_string(),
+            })
+        );
+
+        assert!(send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[payment],
+            0,
+            dest_address,
+            options("penumbra-test"),
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_refuses_to_plan_while_unsynced() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let mut view = MockView::new(fvk, [(0, 20)]);
+        view.sync_height = 900;
+        let payment = Value {
+            amount: 10,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+        let options = |chain_tip| SendOptions {
+            require_synced: Some(SyncRequirement {
+                chain_tip,
+                max_behind: 10,
+            }),
+            ..Default::default()
+        };
+
+        let error = send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[payment],
+            0,
+            dest_address,
+            options(1000),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<StaleSync>(),
+            Some(&StaleSync { behind: 100 })
+        );
+
+        assert!(send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[payment],
+            0,
+            dest_address,
+            options(905),
+        )
+        .await
+        .is_ok());
+    }
+
+    #[test]
+    fn sweep_consolidates_notes_into_one_output() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (dest_address, _dtk) = fvk.incoming().payment_address(1u64.into());
+
+        let records = [10, 20, 30]
+            .into_iter()
+            .enumerate()
+            .map(|(i, amount)| note_record(fvk, 0, amount, *STAKING_TOKEN_ASSET_ID, 1, i as u64))
+            .collect::<Vec<_>>();
+
+        let plan = sweep_asset_plan(
+            OsRng,
+            "test".to_string(),
+            &records,
+            *STAKING_TOKEN_ASSET_ID,
+            dest_address,
+            5,
+        )
+        .unwrap();
+        assert_eq!(plan.spend_plans().count(), 3);
+        let outputs = plan.output_plans().collect::<Vec<_>>();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].value.amount, 55);
+        assert_eq!(outputs[0].dest_address, dest_address);
+
+        assert!(sweep_asset_plan(
+            OsRng,
+            "test".to_string(),
+            &records,
+            *STAKING_TOKEN_ASSET_ID,
+            dest_address,
+            60,
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn send_can_spend_from_fewest_addresses() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+
+        // Address 0 holds four small notes covering the payment by themselves; addresses 1 and 2
+        // hold a larger note each, which would cover it with fewer notes from more addresses.
+        let mut view = MockView::new(fvk, [(1, 6), (2, 6), (0, 3), (0, 3), (0, 3), (0, 3)]);
+        let plan = send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[Value {
+                amount: 12,
+                asset_id: *STAKING_TOKEN_ASSET_ID,
+            }],
+            0,
+            dest_address,
+            SendOptions {
+                strategy: selection::CoinSelectionStrategy::FewestAddresses,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let spent_from = plan
+            .spend_plans()
+            .map(|spend| {
+                fvk.incoming()
+                    .index_for_diversifier(&spend.note.diversifier())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(spent_from, vec![AddressIndex::from(0u64); 4]);
+    }
+
+    #[tokio::test]
+    async fn send_to_two_recipients_of_the_same_denom() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (alice, _dtk) = fvk.incoming().payment_address(8u64.into());
+        let (bob, _dtk) = fvk.incoming().payment_address(9u64.into());
+        let mut view = MockView::new(fvk, [(0, 20), (0, 30)]);
+        let staking = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+
+        let plan = send_to_many(
+            fvk,
+            &mut view,
+            OsRng,
+            &[(alice, staking(15)), (bob, staking(25))],
+            3,
+            SendOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        // Both payments are covered by the same spends, with a single change output.
+        assert_eq!(plan.spend_plans().count(), 2);
+        let outputs = plan
+            .output_plans()
+            .map(|output| (output.dest_address, output.value.amount))
+            .collect::<Vec<_>>();
+        let (change_address, _dtk) = fvk.incoming().payment_address(0u64.into());
+        assert_eq!(outputs, vec![(alice, 15), (bob, 25), (change_address, 7)]);
+        assert_eq!(plan.fee.0, 3);
+    }
+
+    #[tokio::test]
+    async fn send_returns_exact_change() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+        let mut view = MockView::new(fvk, [(0, 40), (0, 70), (0, 100)]);
+        let staking = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+        let options = SendOptions {
+            exact_change: Some(staking(50)),
+            ..Default::default()
+        };
+
+        // Only the notes of 40 and 70 sum to the payment of 55 plus the fee of 5 and change of 50.
+        let plan = send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[staking(55)],
+            5,
+            dest_address,
+            options.clone(),
+        )
+        .await
+        .unwrap();
+        let spent = plan
+            .spend_plans()
+            .map(|spend| spend.note.amount())
+            .sum::<u64>();
+        assert_eq!(spent, 55 + 5 + 50);
+        let change = plan
+            .output_plans()
+            .filter(|output| output.dest_address != dest_address)
+            .map(|output| output.value.amount)
+            .collect::<Vec<_>>();
+        assert_eq!(change, vec![50]);
+
+        assert_eq!(
+            send(
+                fvk,
+                &mut view,
+                OsRng,
+                &[staking(56)],
+                5,
+                dest_address,
+                options,
+            )
+            .await
+            .unwrap_err()
+            .downcast::<PlanError>()
+            .unwrap(),
+            PlanError::NoExactCombination {
+                denom: STAKING_TOKEN_DENOM.to_string(),
+                target: 111,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn send_respects_privacy_budget() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let mut view = MockView::new(fvk, [(0, 2); 6]);
+
+        // Paying 12 needs all six notes, one more than the budget allows.
+        let error = send_within_budget(fvk, &mut view, 12).await.unwrap_err();
+        assert!(error.to_string().contains("consider sweeping"));
+
+        let plan = send_within_budget(fvk, &mut view, 10).await.unwrap();
+        assert_eq!(plan.spend_plans().count(), 5);
+    }
+
+    #[tokio::test]
+    async fn send_enforces_min_output_value() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+        let mut view = MockView::new(fvk, [(0, 20)]);
+        let options = SendOptions {
+            min_output_value: Some(10),
+            ..Default::default()
+        };
+        let payment = |amount| Value {
+            amount,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+
+        let error = send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[payment(5)],
+            0,
+            dest_address,
+            options.clone(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("below the minimum output value"));
+
+        // Change of 5 would be dust, so it's paid as fee instead.
+        let plan = send(
+            fvk,
+            &mut view,
+            OsRng,
+            &[payment(15)],
+            0,
+            dest_address,
+            options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(plan.output_plans().count(), 1);
+        assert_eq!(plan.fee.0, 5);
+    }
+
+    async fn send_within_budget(
+        fvk: &FullViewingKey,
+        view: &mut MockView,
+        amount: u64,
+    ) -> Result<TransactionPlan> {
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+        send(
+            fvk,
+            view,
+            OsRng,
+            &[Value {
+                amount,
+                asset_id: *STAKING_TOKEN_ASSET_ID,
+            }],
+            0,
+            dest_address,
+            SendOptions {
+                strategy: selection::CoinSelectionStrategy::SmallestFirst,
+                privacy_budget: Some(selection::PrivacyBudget {
+                    max_notes: 5,
+                    max_addresses: 1,
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    /// Plan a payment of `payment` to `dest_address` with no fee, drawing randomness from `rng`.
+    async fn send_seeded(
+        fvk: &FullViewingKey,
+        view: &mut MockView,
+        rng: &mut CountingRng<StdRng>,
+        payment: Value,
+        dest_address: Address,
+        strategy: selection::CoinSelectionStrategy,
+    ) -> TransactionPlan {
+        send(
+            fvk,
+            view,
+            rng,
+            &[payment],
+            0,
+            dest_address,
+            SendOptions {
+                strategy,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_draws_randomness_in_documented_order() {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        let fvk = sk.full_viewing_key();
+        let (dest_address, _dtk) = fvk.incoming().payment_address(9u64.into());
+        let payment = Value {
+            amount: 10,
+            asset_id: *STAKING_TOKEN_ASSET_ID,
+        };
+
+        let mut view = MockView::new(fvk, [(0, 20), (0, 5), (0, 7)]);
+
+        let mut rng = CountingRng::new(StdRng::seed_from_u64(1));
+        let plan = send_seeded(
+            fvk,
+            &mut view,
+            &mut rng,
+            payment,
+            dest_address,
+            selection::CoinSelectionStrategy::Deterministic,
+        )
+        .await;
+
+        // Replaying the documented order with the same seed gives exactly the same plan: the
+        // payment output, a spend of each of the two smallest notes, then the change output.
+        let spent = [view.notes[1].clone(), view.notes[2].clone()];
+        let (change_address, _dtk) = fvk.incoming().payment_address(0u64.into());
+        let mut expected_rng = CountingRng::new(StdRng::seed_from_u64(1));
+        let expected = TransactionPlan {
+            chain_id: "penumbra-test".to_string(),
+            fee: Fee(0),
+            actions: vec![
+                OutputPlan::new(
+                    &mut expected_rng,
+                    payment,
+                    dest_address,
+                    MemoPlaintext::default(),
+                )
+                .into(),
+                SpendPlan::new(&mut expected_rng, spent[0].note.clone(), spent[0].position).into(),
+                SpendPlan::new(&mut expected_rng, spent[1].note.clone(), spent[1].position).into(),
+                OutputPlan::new(
+                    &mut expected_rng,
+                    Value {
+                        amount: 2,
+                        asset_id: *STAKING_TOKEN_ASSET_ID,
+                    },
+                    change_address,
+                    MemoPlaintext::change(),
+                )
+                .into(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(rng.draws, expected_rng.draws);
+        assert_eq!(plan.encode_to_vec(), expected.encode_to_vec());
+
+        // Random selection also draws for its shuffle, but is just as reproducible.
+        let mut rng_a = CountingRng::new(StdRng::seed_from_u64(2));
+        let mut rng_b = CountingRng::new(StdRng::seed_from_u64(2));
+        let plan_a = send_seeded(
+            fvk,
+            &mut view,
+            &mut rng_a,
+            payment,
+            dest_address,
+            selection::CoinSelectionStrategy::Random,
+        )
+        .await;
+        let plan_b = send_seeded(
+            fvk,
+            &mut view,
+            &mut rng_b,
+            payment,
+            dest_address,
+            selection::CoinSelectionStrategy::Random,
+        )
+        .await;
+        assert_eq!(rng_a.draws, rng_b.draws);
+        assert_eq!(plan_a.encode_to_vec(), plan_b.encode_to_vec());
+    }
+}
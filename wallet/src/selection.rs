@@ -0,0 +1,591 @@
+//! Helpers for selecting which notes to spend.
+
+use std::collections::BTreeMap;
+
+use penumbra_crypto::{asset, Value, STAKING_TOKEN_ASSET_ID};
+use penumbra_view::NoteRecord;
+use rand::seq::SliceRandom;
+use rand_core::RngCore;
+
+/// The maximum number of notes [`exact_subset`] will combine, by default.
+pub const MAX_EXACT_SUBSET_SIZE: usize = 16;
+
+/// The maximum number of search steps taken by [`exact_subset`] before giving up.
+const MAX_SEARCH_STEPS: usize = 100_000;
+
+/// Find a subset of at most `max_items` of the given `amounts` which sums to exactly `target`,
+/// returning the indices of the chosen amounts.
+///
+/// This is a bounded depth-first search over the amounts in descending order, so it returns
+/// `None` both when no such subset exists and when the search gives up.
+pub fn exact_subset(amounts: &[u64], target: u64, max_items: usize) -> Option<Vec<usize>> {
+    let mut order = (0..amounts.len())
+        .filter(|&i| amounts[i] > 0)
+        .collect::<Vec<_>>();
+    order.sort_by(|&a, &b| amounts[b].cmp(&amounts[a]));
+
+    // The sum of each suffix of the ordered amounts, used to prune branches which can no longer
+    // reach the target.
+    let mut suffix_sums = vec![0u64; order.len() + 1];
+    for i in (0..order.len()).rev() {
+        suffix_sums[i] = suffix_sums[i + 1].saturating_add(amounts[order[i]]);
+    }
+
+    let mut search = ExactSubsetSearch {
+        amounts,
+        order: &order,
+        suffix_sums: &suffix_sums,
+        max_items,
+        chosen: Vec::new(),
+        steps: 0,
+    };
+
+    if search.search(0, target) {
+        Some(search.chosen.into_iter().map(|i| order[i]).collect())
+    } else {
+        None
+    }
+}
+
+struct ExactSubsetSearch<'a> {
+    amounts: &'a [u64],
+    order: &'a [usize],
+    suffix_sums: &'a [u64],
+    max_items: usize,
+    chosen: Vec<usize>,
+    steps: usize,
+}
+
+impl ExactSubsetSearch<'_> {
+    fn search(&mut self, start: usize, remaining: u64) -> bool {
+        if remaining == 0 {
+            return true;
+        }
+        if self.chosen.len() == self.max_items || self.suffix_sums[start] < remaining {
+            return false;
+        }
+
+        for i in start..self.order.len() {
+            if self.steps >= MAX_SEARCH_STEPS || self.suffix_sums[i] < remaining {
+                return false;
+            }
+            self.steps += 1;
+
+            let amount = self.amounts[self.order[i]];
+            if amount > remaining {
+                continue;
+            }
+
+            self.chosen.push(i);
+            if self.search(i + 1, remaining - amount) {
+                return true;
+            }
+            self.chosen.pop();
+        }
+
+        false
+    }
+}
+
+/// The order in which notes are accumulated when selecting notes to cover an amount.
+///
+/// The default is [`CoinSelectionStrategy::Random`], which reveals the least about which notes
+/// the wallet holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoinSelectionStrategy {
+    /// Accumulate notes in a random order.
+    Random,
+    /// Accumulate the largest notes first, spending as few notes as possible.
+    LargestFirst,
+    /// Accumulate the smallest notes first, consolidating dust as a side effect.
+    SmallestFirst,
+    /// Accumulate notes in order of their amounts and then their note commitments, so the same
+    /// notes always select the same notes, whatever order they are given in, e.g. for
+    /// reproducible tests.
+    ///
+    /// Unlike [`CoinSelectionStrategy::Random`], this makes which notes are spent predictable to
+    /// anyone who knows the wallet's notes, so it trades privacy for reproducibility and should
+    /// not be used for real payments.
+    Deterministic,
+    /// Spend notes sent to as few distinct addresses as possible, even if that means spending
+    /// more notes; see [`fewest_addresses`].
+    FewestAddresses,
+}
+
+impl Default for CoinSelectionStrategy {
+    fn default() -> Self {
+        CoinSelectionStrategy::Random
+    }
+}
+
+impl CoinSelectionStrategy {
+    /// Select from the given `notes` until they cover `target`, returning the indices of the
+    /// chosen notes, or `None` if all of them together don't cover `target`.
+    pub fn select<R: RngCore>(
+        &self,
+        notes: &[NoteRecord],
+        target: u64,
+        rng: &mut R,
+    ) -> Option<Vec<usize>> {
+        let amounts = notes
+            .iter()
+            .map(|record| record.note.amount())
+            .collect::<Vec<_>>();
+        let mut order = (0..amounts.len()).collect::<Vec<_>>();
+        match self {
+            CoinSelectionStrategy::Random => order.shuffle(rng),
+            CoinSelectionStrategy::LargestFirst => {
+                order.sort_by_key(|&i| std::cmp::Reverse(amounts[i]))
+            }
+            CoinSelectionStrategy::SmallestFirst => order.sort_by_key(|&i| amounts[i]),
+            CoinSelectionStrategy::Deterministic => {
+                order.sort_by_key(|&i| (amounts[i], notes[i].note_commitment.0.to_bytes()))
+            }
+            CoinSelectionStrategy::FewestAddresses => {
+                let notes = notes
+                    .iter()
+                    .map(|record| (record.address_index, record.note.amount()))
+                    .collect::<Vec<_>>();
+                return fewest_addresses(&notes, target).map(|selection| selection.indices);
+            }
+        }
+
+        let mut chosen = Vec::new();
+        let mut covered = 0u64;
+        for i in order {
+            if covered >= target {
+                break;
+            }
+            covered = covered.saturating_add(amounts[i]);
+            chosen.push(i);
+        }
+
+        if covered >= target {
+            Some(chosen)
+        } else {
+            None
+        }
+    }
+}
+
+/// A selection of notes touching as few distinct addresses as possible.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressSelection {
+    /// The indices of the chosen notes.
+    pub indices: Vec<usize>,
+    /// The number of distinct addresses the chosen notes were sent to.
+    pub addresses_touched: usize,
+}
+
+/// Select notes, given as `(address, amount)` pairs, covering at least `target` while touching
+/// the fewest distinct addresses, returning `None` if the notes don't cover `target` at all.
+///
+/// Since spending notes from several addresses in one transaction links those addresses, this
+/// optimizes for privacy rather than for the number of notes spent.
+pub fn fewest_addresses<A: Ord>(notes: &[(A, u64)], target: u64) -> Option<AddressSelection> {
+    let mut by_address = BTreeMap::<&A, Vec<usize>>::new();
+    for (i, (address, _)) in notes.iter().enumerate() {
+        by_address.entry(address).or_default().push(i);
+    }
+
+    // Any `k` addresses cover at most the sum of the `k` largest per-address totals, so taking
+    // the addresses with the largest totals first touches the fewest addresses overall.
+    let mut groups = by_address.into_values().collect::<Vec<_>>();
+    let total = |group: &Vec<usize>| group.iter().map(|&i| notes[i].1).sum::<u64>();
+    groups.sort_by_key(|group| std::cmp::Reverse(total(group)));
+
+    let mut chosen_groups = Vec::new();
+    let mut covered = 0u64;
+    for group in groups {
+        if covered >= target {
+            break;
+        }
+        covered = covered.saturating_add(total(&group));
+        chosen_groups.push(group);
+    }
+    if covered < target {
+        return None;
+    }
+
+    // Within the chosen addresses, spend the largest notes first until the target is covered.
+    let addresses_touched = chosen_groups.len();
+    let mut candidates = chosen_groups.into_iter().flatten().collect::<Vec<_>>();
+    candidates.sort_by_key(|&i| std::cmp::Reverse(notes[i].1));
+
+    let mut indices = Vec::new();
+    let mut remaining = target;
+    for i in candidates {
+        if remaining == 0 {
+            break;
+        }
+        remaining = remaining.saturating_sub(notes[i].1);
+        indices.push(i);
+    }
+
+    Some(AddressSelection {
+        indices,
+        addresses_touched,
+    })
+}
+
+/// Split `count` notes into consecutive batches of at most `max_spends` notes each, for
+/// consolidating them over several transactions.
+///
+/// A trailing batch of a single note is dropped, since there is nothing to consolidate it with.
+pub fn consolidation_batches(count: usize, max_spends: usize) -> Vec<std::ops::Range<usize>> {
+    if max_spends < 2 {
+        return Vec::new();
+    }
+
+    (0..count)
+        .step_by(max_spends)
+        .map(|start| start..(start + max_spends).min(count))
+        .filter(|batch| batch.len() > 1)
+        .collect()
+}
+
+/// The number of notes to merge into one so that `note_count` notes are brought down to
+/// `max_notes`, or `None` if there are no more than `max_notes` notes already.
+pub fn notes_to_merge(note_count: usize, max_notes: usize) -> Option<usize> {
+    if note_count <= max_notes {
+        return None;
+    }
+
+    // Merging n notes into one leaves n - 1 fewer notes.
+    Some((note_count - max_notes + 1).min(note_count))
+}
+
+/// A cap on how many notes, and notes from how many distinct addresses, a single spend may
+/// combine, since each additional note or address combined reveals more about the wallet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrivacyBudget {
+    pub max_notes: usize,
+    pub max_addresses: usize,
+}
+
+impl PrivacyBudget {
+    /// Select notes, given as `(address, amount)` pairs, covering at least `target` within this
+    /// budget, returning the indices of the chosen notes.
+    pub fn select<A: Ord>(&self, notes: &[(A, u64)], target: u64) -> anyhow::Result<Vec<usize>> {
+        // Try touching as few addresses as possible, then spending as few notes as possible.
+        let fewest_addresses = fewest_addresses(notes, target)
+            .ok_or_else(|| anyhow::anyhow!("insufficient funds to cover {}", target))?
+            .indices;
+        let mut largest_first = (0..notes.len()).collect::<Vec<_>>();
+        largest_first.sort_by_key(|&i| std::cmp::Reverse(notes[i].1));
+        let mut covered = 0u64;
+        let fewest_notes = largest_first
+            .into_iter()
+            .take_while(|&i| {
+                let needed = covered < target;
+                covered = covered.saturating_add(notes[i].1);
+                needed
+            })
+            .collect::<Vec<_>>();
+
+        [fewest_addresses, fewest_notes]
+            .into_iter()
+            .find(|indices| self.allows(notes, indices))
+            .ok_or_else(|| self.exceeded(target))
+    }
+
+    /// Check that spending the notes at `indices` among `notes`, given as `(address, amount)`
+    /// pairs, to cover `target` stays within this budget.
+    pub fn check<A: Ord>(
+        &self,
+        notes: &[(A, u64)],
+        indices: &[usize],
+        target: u64,
+    ) -> anyhow::Result<()> {
+        if self.allows(notes, indices) {
+            Ok(())
+        } else {
+            Err(self.exceeded(target))
+        }
+    }
+
+    /// Whether spending the notes at `indices` among `notes`, given as `(address, amount)` pairs,
+    /// stays within this budget.
+    pub fn allows<A: Ord>(&self, notes: &[(A, u64)], indices: &[usize]) -> bool {
+        let addresses = indices
+            .iter()
+            .map(|&i| &notes[i].0)
+            .collect::<std::collections::BTreeSet<_>>();
+        indices.len() <= self.max_notes && addresses.len() <= self.max_addresses
+    }
+
+    fn exceeded(&self, target: u64) -> anyhow::Error {
+        anyhow::anyhow!(
+            "cannot cover {} with at most {} notes from at most {} addresses; consider sweeping notes together first",
+            target,
+            self.max_notes,
+            self.max_addresses
+        )
+    }
+}
+
+/// Compute the change owed for each asset, given the values of the notes selected as inputs and
+/// the amount of each asset `required` by the outputs and fee.
+///
+/// Change is computed separately for every distinct asset among the inputs, so a spend combining
+/// several assets yields one change amount per asset, omitting assets with no change left over.
+pub fn change_per_asset(
+    inputs: &[Value],
+    required: &BTreeMap<asset::Id, u64>,
+) -> anyhow::Result<BTreeMap<asset::Id, u64>> {
+    let mut spent = BTreeMap::<asset::Id, u64>::new();
+    for input in inputs {
+        *spent.entry(input.asset_id).or_default() += input.amount;
+    }
+
+    for (asset_id, amount) in required {
+        if spent.get(asset_id).copied().unwrap_or(0) < *amount {
+            return Err(anyhow::anyhow!(
+                "selected inputs do not cover {} of asset {}",
+                amount,
+                asset_id
+            ));
+        }
+    }
+
+    Ok(spent
+        .into_iter()
+        .map(|(asset_id, amount)| {
+            let required = required.get(&asset_id).copied().unwrap_or(0);
+            (asset_id, amount - required)
+        })
+        .filter(|(_, change)| *change > 0)
+        .collect())
+}
+
+/// Remove staking token change below `min_output_value` from the per-asset `change`, returning
+/// the amount removed, which is paid as fee instead of creating a dust note.
+///
+/// Change in other assets is kept whatever its amount, since it can't be paid as fee.
+pub fn absorb_dust_change(change: &mut BTreeMap<asset::Id, u64>, min_output_value: u64) -> u64 {
+    match change.get(&*STAKING_TOKEN_ASSET_ID) {
+        Some(&amount) if amount < min_output_value => {
+            change.remove(&*STAKING_TOKEN_ASSET_ID);
+            amount
+        }
+        _ => 0,
+    }
+}
+
+/// Split `total` into notes of the given `standard_values`, using as few notes as a greedy
+/// largest-first split allows, returning the amounts of those notes and any remainder which
+/// can't be expressed in standard values.
+pub fn split_into_standard_values(total: u64, standard_values: &[u64]) -> (Vec<u64>, u64) {
+    let mut standard_values = standard_values
+        .iter()
+        .copied()
+        .filter(|&value| value > 0)
+        .collect::<Vec<_>>();
+    standard_values.sort_unstable_by(|a, b| b.cmp(a));
+    standard_values.dedup();
+
+    let mut remaining = total;
+    let mut amounts = Vec::new();
+    for value in standard_values {
+        let count = remaining / value;
+        amounts.extend(std::iter::repeat(value).take(count as usize));
+        remaining -= count * value;
+    }
+
+    (amounts, remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use penumbra_crypto::keys::{SeedPhrase, SpendKey};
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::test_utils::note_record;
+
+    /// Records of notes of the given staking token `amounts`, all to the same address.
+    fn records(amounts: &[u64]) -> Vec<NoteRecord> {
+        let sk = SpendKey::from_seed_phrase(SeedPhrase::generate(&mut OsRng), 0);
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, &amount)| {
+                note_record(
+                    sk.full_viewing_key(),
+                    0,
+                    amount,
+                    *STAKING_TOKEN_ASSET_ID,
+                    1,
+                    i as u64,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exact_subset_finds_exact_combination() {
+        let amounts = [7, 3, 20, 5, 11];
+        let chosen = exact_subset(&amounts, 19, MAX_EXACT_SUBSET_SIZE).unwrap();
+        assert_eq!(chosen.iter().map(|&i| amounts[i]).sum::<u64>(), 19);
+    }
+
+    #[test]
+    fn exact_subset_fails_without_exact_combination() {
+        assert_eq!(exact_subset(&[10, 20, 40], 25, MAX_EXACT_SUBSET_SIZE), None);
+        // 3 + 5 + 7 = 15, but not within two notes.
+        assert_eq!(exact_subset(&[3, 5, 7], 15, 2), None);
+    }
+
+    #[test]
+    fn exact_subset_prefers_single_note() {
+        let amounts = [4, 6, 10, 3, 7];
+        assert_eq!(
+            exact_subset(&amounts, 10, MAX_EXACT_SUBSET_SIZE),
+            Some(vec![2])
+        );
+    }
+
+    #[test]
+    fn deterministic_selection_is_reproducible() {
+        let mut notes = records(&[5, 3, 8, 3, 1, 3]);
+        let mut rng = OsRng;
+        let select = |notes: &[NoteRecord]| {
+            CoinSelectionStrategy::Deterministic
+                .select(notes, 6, &mut OsRng)
+                .unwrap()
+                .into_iter()
+                .map(|i| notes[i].note_commitment)
+                .collect::<Vec<_>>()
+        };
+
+        // The smallest notes are chosen, and of the three notes of 3, the two with the smallest
+        // commitments are, however the notes are ordered.
+        let mut threes = notes
+            .iter()
+            .filter(|record| record.note.amount() == 3)
+            .map(|record| record.note_commitment)
+            .collect::<Vec<_>>();
+        threes.sort_by_key(|commitment| commitment.0.to_bytes());
+        let expected = vec![notes[4].note_commitment, threes[0], threes[1]];
+
+        for _ in 0..10 {
+            notes.shuffle(&mut rng);
+            assert_eq!(select(&notes), expected);
+        }
+    }
+
+    #[test]
+    fn fewest_addresses_prefers_fewer_addresses_over_fewer_notes() {
+        // Address 0 holds four small notes which cover the target together; addresses 1 and 2
+        // hold one larger note each, which cover it with fewer notes, but only by touching both
+        // addresses.
+        let notes = [(1, 6), (2, 6), (0, 3), (0, 3), (0, 3), (0, 3)];
+        let selection = fewest_addresses(&notes, 12).unwrap();
+        assert_eq!(selection.addresses_touched, 1);
+        assert!(selection.indices.iter().all(|&i| notes[i].0 == 0));
+        assert_eq!(selection.indices.len(), 4);
+    }
+
+    #[test]
+    fn fewest_addresses_fails_without_enough_value() {
+        assert_eq!(fewest_addresses(&[(0, 5), (1, 5)], 11), None);
+    }
+
+    #[test]
+    fn privacy_budget_limits_note_count() {
+        let notes = [(0, 2), (0, 2), (0, 2), (0, 2), (0, 2), (0, 2)];
+        let budget = PrivacyBudget {
+            max_notes: 5,
+            max_addresses: 1,
+        };
+        assert!(budget.select(&notes, 12).is_err());
+        assert_eq!(budget.select(&notes, 10).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn consolidation_batches_respect_max_spends() {
+        assert_eq!(consolidation_batches(12, 5), vec![0..5, 5..10, 10..12]);
+        assert_eq!(consolidation_batches(11, 5), vec![0..5, 5..10]);
+        assert!(consolidation_batches(12, 1).is_empty());
+    }
+
+    #[test]
+    fn consolidation_suggested_above_threshold() {
+        assert_eq!(notes_to_merge(10, 10), None);
+        assert_eq!(notes_to_merge(11, 10), Some(2));
+        assert_eq!(notes_to_merge(15, 10), Some(6));
+        assert_eq!(notes_to_merge(3, 0), Some(3));
+    }
+
+    #[test]
+    fn change_per_asset_balances_each_asset() {
+        let a = asset::REGISTRY.parse_denom("upenumbra").unwrap().id();
+        let b = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+        let value = |amount, asset_id| Value { amount, asset_id };
+
+        let inputs = [value(10, a), value(5, b), value(7, a), value(4, b)];
+        let required = BTreeMap::from([(a, 12), (b, 9)]);
+        let change = change_per_asset(&inputs, &required).unwrap();
+        assert_eq!(change, BTreeMap::from([(a, 5)]));
+
+        let required = BTreeMap::from([(a, 12), (b, 6)]);
+        let change = change_per_asset(&inputs, &required).unwrap();
+        assert_eq!(change, BTreeMap::from([(a, 5), (b, 3)]));
+
+        let required = BTreeMap::from([(a, 18)]);
+        assert!(change_per_asset(&inputs, &required).is_err());
+    }
+
+    #[test]
+    fn largest_first_spends_fewest_notes() {
+        let amounts = [1, 1, 1, 10];
+        let notes = records(&amounts);
+        let mut rng = OsRng;
+
+        let largest_first = CoinSelectionStrategy::LargestFirst
+            .select(&notes, 9, &mut rng)
+            .unwrap();
+        assert_eq!(largest_first, vec![3]);
+
+        let smallest_first = CoinSelectionStrategy::SmallestFirst
+            .select(&notes, 9, &mut rng)
+            .unwrap();
+        assert_eq!(smallest_first.len(), 4);
+
+        let random = CoinSelectionStrategy::Random
+            .select(&notes, 9, &mut rng)
+            .unwrap();
+        assert!(random.iter().map(|&i| amounts[i]).sum::<u64>() >= 9);
+        assert!(random.contains(&3));
+
+        assert_eq!(
+            CoinSelectionStrategy::LargestFirst.select(&notes, 14, &mut rng),
+            None
+        );
+    }
+
+    #[test]
+    fn dust_change_is_absorbed_into_fee() {
+        let other = asset::REGISTRY.parse_denom("ugm").unwrap().id();
+
+        let mut change = BTreeMap::from([(*STAKING_TOKEN_ASSET_ID, 3), (other, 2)]);
+        assert_eq!(absorb_dust_change(&mut change, 10), 3);
+        assert_eq!(change, BTreeMap::from([(other, 2)]));
+
+        let mut change = BTreeMap::from([(*STAKING_TOKEN_ASSET_ID, 10)]);
+        assert_eq!(absorb_dust_change(&mut change, 10), 0);
+        assert_eq!(change.len(), 1);
+    }
+
+    #[test]
+    fn odd_values_split_into_standard_values() {
+        // Notes of 3, 17 and 95 total 115.
+        let (amounts, remainder) = split_into_standard_values(3 + 17 + 95, &[1, 100, 10]);
+        assert_eq!(amounts, vec![100, 10, 1, 1, 1, 1, 1]);
+        assert_eq!(remainder, 0);
+
+        let (amounts, remainder) = split_into_standard_values(115, &[10, 100]);
+        assert_eq!(amounts, vec![100, 10]);
+        assert_eq!(remainder, 5);
+    }
+}
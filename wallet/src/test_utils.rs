@@ -0,0 +1,220 @@
+//! Fixtures shared by the tests of this crate.
+
+use std::pin::Pin;
+
+use anyhow::Result;
+use futures::Stream;
+use penumbra_chain::{params::ChainParams, NoteSource};
+use penumbra_crypto::{
+    asset,
+    keys::{AddressIndex, FullViewingKeyHash},
+    note, FullViewingKey, Note, Nullifier, Value, STAKING_TOKEN_DENOM,
+};
+use penumbra_proto::view as pb;
+use penumbra_tct as tct;
+use penumbra_transaction::WitnessData;
+use penumbra_view::{NoteRecord, QuarantinedNoteRecord, StatusStreamResponse, ViewClient};
+use rand_core::{CryptoRng, OsRng, RngCore};
+use tonic::async_trait;
+
+/// A record of a freshly generated note of `amount` of `asset_id` to the address of `fvk` with
+/// index `address_index`, created at `height` and placed at `position` in the note commitment
+/// tree.
+pub fn note_record(
+    fvk: &FullViewingKey,
+    address_index: u64,
+    amount: u64,
+    asset_id: asset::Id,
+    height: u64,
+    position: u64,
+) -> NoteRecord {
+    let address_index = AddressIndex::from(address_index);
+    let (address, _dtk) = fvk.incoming().payment_address(address_index);
+    let note = Note::generate(&mut OsRng, &address, Value { amount, asset_id });
+    let position = position.into();
+    NoteRecord {
+        note_commitment: note.commit(),
+        nullifier: fvk.derive_nullifier(position, &note.commit()),
+        address_index,
+        height_created: height,
+        height_spent: None,
+        position,
+        source: NoteSource::Genesis,
+        note,
+    }
+}
+
+/// An RNG counting the number of draws made from it, for checking that planning consumes
+/// randomness in a fixed pattern.
+pub struct CountingRng<R> {
+    inner: R,
+    pub draws: usize,
+}
+
+impl<R> CountingRng<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, draws: 0 }
+    }
+}
+
+impl<R: RngCore> RngCore for CountingRng<R> {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.inner.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.inner.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.draws += 1;
+        self.inner.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand_core::Error> {
+        self.draws += 1;
+        self.inner.try_fill_bytes(dest)
+    }
+}
+
+impl<R: CryptoRng> CryptoRng for CountingRng<R> {}
+
+/// A view service holding a fixed set of unspent staking token notes, all witnessed in its note
+/// commitment tree.
+pub struct MockView {
+    pub notes: Vec<NoteRecord>,
+    pub nct: tct::Tree,
+    /// The height the view reports having synced to, 1 by default, when its notes were created.
+    pub sync_height: u64,
+}
+
+impl MockView {
+    /// A view of notes of the given amounts to the addresses of `fvk` with the given indices,
+    /// inserted into the note commitment tree in order.
+    pub fn new(fvk: &FullViewingKey, notes: impl IntoIterator<Item = (u64, u64)>) -> Self {
+        let mut nct = tct::Tree::new();
+        let notes = notes
+            .into_iter()
+            .map(|(address_index, amount)| {
+                let position = nct.position().unwrap().into();
+                let record = note_record(
+                    fvk,
+                    address_index,
+                    amount,
+                    STAKING_TOKEN_DENOM.id(),
+                    1,
+                    position,
+                );
+                nct.insert(tct::Witness::Keep, record.note_commitment)
+                    .unwrap();
+                record
+            })
+            .collect();
+        Self {
+            notes,
+            nct,
+            sync_height: 1,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ViewClient for MockView {
+    async fn status(&mut self, _fvk_hash: FullViewingKeyHash) -> Result<pb::StatusResponse> {
+        Ok(pb::StatusResponse {
+            sync_height: self.sync_height,
+            catching_up: false,
+        })
+    }
+
+    async fn status_stream(
+        &mut self,
+        _fvk_hash: FullViewingKeyHash,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StatusStreamResponse>> + Send + 'static>>> {
+        unimplemented!()
+    }
+
+    async fn chain_params(&mut self) -> Result<ChainParams> {
+        Ok(ChainParams {
+            chain_id: "penumbra-test".to_string(),
+            ..Default::default()
+        })
+    }
+
+    async fn notes(&mut self, request: pb::NotesRequest) -> Result<Vec<NoteRecord>> {
+        let asset_id = request.asset_id.map(asset::Id::try_from).transpose()?;
+        let address_index = request
+            .address_index
+            .map(AddressIndex::try_from)
+            .transpose()?;
+        Ok(self
+            .notes
+            .iter()
+            .filter(|record| asset_id.map_or(true, |id| record.note.asset_id() == id))
+            .filter(|record| address_index.map_or(true, |index| record.address_index == index))
+            .cloned()
+            .collect())
+    }
+
+    async fn quarantined_notes(
+        &mut self,
+        _request: pb::QuarantinedNotesRequest,
+    ) -> Result<Vec<QuarantinedNoteRecord>> {
+        unimplemented!()
+    }
+
+    async fn note_by_commitment(
+        &mut self,
+        _fvk_hash: FullViewingKeyHash,
+        _note_commitment: note::Commitment,
+    ) -> Result<NoteRecord> {
+        unimplemented!()
+    }
+
+    async fn nullifier_status(
+        &mut self,
+        _fvk_hash: FullViewingKeyHash,
+        _nullifier: Nullifier,
+    ) -> Result<bool> {
+        unimplemented!()
+    }
+
+    async fn await_nullifier(
+        &mut self,
+        _fvk_hash: FullViewingKeyHash,
+        _nullifier: Nullifier,
+    ) -> Result<()> {
+        unimplemented!()
+    }
+
+    async fn await_note_by_commitment(
+        &mut self,
+        _fvk_hash: FullViewingKeyHash,
+        _note_commitment: note::Commitment,
+    ) -> Result<NoteRecord> {
+        unimplemented!()
+    }
+
+    async fn witness(&mut self, request: pb::WitnessRequest) -> Result<WitnessData> {
+        let note_commitment_proofs = request
+            .note_commitments
+            .into_iter()
+            .map(|commitment| {
+                let commitment = note::Commitment::try_from(commitment)?;
+                self.nct
+                    .witness(commitment)
+                    .ok_or_else(|| anyhow::anyhow!("note commitment {:?} missing", commitment))
+            })
+            .collect::<Result<_>>()?;
+        Ok(WitnessData {
+            anchor: self.nct.root(),
+            note_commitment_proofs,
+        })
+    }
+
+    async fn assets(&mut self) -> Result<asset::Cache> {
+        Ok([STAKING_TOKEN_DENOM.clone()].into_iter().collect())
+    }
+}